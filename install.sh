@@ -2,6 +2,8 @@
 
 TRACKER_BASE="$HOME/.tracker"
 TRACKER_INIT="$TRACKER_BASE/.tracker.rc"
+TRACKER_ZSH_DIR="$TRACKER_BASE/zsh"
+TRACKER_FISH_DIR="$TRACKER_BASE/fish"
 
 if [[ ! -d "$TRACKER_BASE" ]]; then
     mkdir "$TRACKER_BASE"
@@ -9,6 +11,18 @@ fi
 
 cp ./startup-files/tracker.rc "$TRACKER_INIT"
 
+if [[ ! -d "$TRACKER_ZSH_DIR" ]]; then
+    mkdir "$TRACKER_ZSH_DIR"
+fi
+cp ./startup-files/tracker.zsh "$TRACKER_ZSH_DIR/.zshrc"
+
+if [[ ! -d "$TRACKER_FISH_DIR" ]]; then
+    mkdir "$TRACKER_FISH_DIR"
+fi
+cp ./startup-files/tracker.fish "$TRACKER_FISH_DIR/tracker.fish"
+
+cp ./startup-files/tracker.tmux "$TRACKER_BASE/tracker.tmux"
+
 cargo install --path .
 
 echo "Installed. Please run 'tracker'."
@@ -2,6 +2,7 @@ use std::fmt;
 use std::fs::remove_file;
 use std::path::PathBuf;
 
+use crate::filter::Filter;
 use crate::utils::*;
 
 #[cfg(target_os = "macos")]
@@ -84,6 +85,7 @@ pub struct BrowserHistControl {
     database: PathBuf,              /* database of the managed history */
     raw_query: String,              /* raw query of the managed history */
     l_timestamp: u64,               /* last entry timestamp of the managed history */
+    filter: Filter,                 /* urls to track */
 }
 
 impl BrowserHistControl {
@@ -114,6 +116,7 @@ impl BrowserHistControl {
             database,
             raw_query,
             l_timestamp,
+            filter: Filter::default(),
         };
         ctrl.reset(from);
         Ok(ctrl)
@@ -160,11 +163,13 @@ impl BrowserHistControl {
             ))
         })?;
 
-        let n = history.len();
-        if n != 0 {
-            self.l_timestamp = history.last().unwrap().timestamp;
-            self.history.extend(history);
+        if let Some(last) = history.last() {
+            self.l_timestamp = last.timestamp;
         }
+        history.retain(|entry| self.filter.keep_url(&entry.url, entry.timestamp));
+
+        let n = history.len();
+        self.history.extend(history);
 
         Ok(n)
     }
@@ -182,6 +187,10 @@ impl BrowserHistControl {
         }
     }
 
+    pub fn set_filter(&mut self, filter: Filter) {
+        self.filter = filter;
+    }
+
     pub fn history(&self) -> &Vec<BrowserHistEntry> {
         &self.history
     }
@@ -1,12 +1,22 @@
 use std::fmt;
 use std::fs::read_to_string;
 use std::net::IpAddr;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use clap;
 use regex;
 
 use crate::browser::Browser;
+#[cfg(not(target_os = "windows"))]
+use crate::control::Request;
+use crate::console::output::DEFAULT_MAX_OUTPUT;
+use crate::console::Shell;
+use crate::filter::Filter;
+use crate::elastic::{check_index_pattern, is_cloud_id, is_index_pattern, Auth, Scheme};
+use crate::sink::SinkKind;
+#[cfg(not(target_os = "windows"))]
+use crate::nest::Nesting;
+use crate::utils::path_expand;
 
 #[allow(dead_code)]
 const TRACKER_CONF: &str = "/etc/tracker.conf"; // todo: use this as default cfg file
@@ -24,15 +34,46 @@ impl std::error::Error for CliError {}
 
 #[derive(Clone, Debug)]
 pub struct Cli {
-    pub host: IpAddr,
+    pub scheme: Scheme,
+    pub host: String,
     pub port: u64,
+    pub nodes: Vec<String>,
+    pub cloud_id: Option<String>,
+    pub auth: Option<Auth>,
+    pub ca_cert: Option<PathBuf>,
+    pub verify_certs: bool,
     pub index: String,
+    pub data_stream: bool,
+    pub ilm_policy: Option<String>,
     pub interface: String,
     pub browser: Option<Browser>,
+    pub shell: Shell,
+    pub max_output: usize,
+    pub redact: Vec<String>,
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    pub outputs: Vec<SinkKind>,
+    pub engagement: Option<String>,
+    pub tags: Vec<String>,
 }
 
 impl Cli {
     pub fn new() -> Result<Cli, CliError> {
+        Cli::from_args(&std::env::args().collect::<Vec<String>>())
+    }
+
+    /**
+     * Parse `args`, the first being the program name shown in the usage.
+     */
+    pub fn from_args(args: &[String]) -> Result<Cli, CliError> {
+        Cli::parse(args, &|var| std::env::var(var).ok())
+    }
+
+    /**
+     * Parse `args`, reading the secrets named by environment variables with
+     * `env`.
+     */
+    fn parse(args: &[String], env: &dyn Fn(&str) -> Option<String>) -> Result<Cli, CliError> {
         let args = clap::App::new("tracker")
             .arg(
                 clap::Arg::with_name("index")
@@ -56,6 +97,76 @@ impl Cli {
                     .number_of_values(1)
                     .default_value("9200"),
             )
+            .arg(
+                clap::Arg::with_name("scheme")
+                    .long("scheme")
+                    .number_of_values(1)
+                    .possible_values(&["http", "https"])
+                    .default_value("http"),
+            )
+            .arg(
+                clap::Arg::with_name("data-stream")
+                    .long("data-stream"),
+            )
+            .arg(
+                clap::Arg::with_name("ilm-policy")
+                    .long("ilm-policy")
+                    .number_of_values(1),
+            )
+            .arg(
+                clap::Arg::with_name("node")
+                    .long("node")
+                    .short("n")
+                    .multiple(true)
+                    .number_of_values(1),
+            )
+            .arg(
+                clap::Arg::with_name("cloud-id")
+                    .long("cloud-id")
+                    .number_of_values(1)
+                    .conflicts_with("node"),
+            )
+            .arg(
+                clap::Arg::with_name("user")
+                    .long("user")
+                    .short("u")
+                    .number_of_values(1),
+            )
+            .arg(
+                clap::Arg::with_name("password-env")
+                    .long("password-env")
+                    .number_of_values(1)
+                    .requires("user")
+                    .conflicts_with("password-file"),
+            )
+            .arg(
+                clap::Arg::with_name("password-file")
+                    .long("password-file")
+                    .number_of_values(1)
+                    .requires("user"),
+            )
+            .arg(
+                clap::Arg::with_name("api-key-env")
+                    .long("api-key-env")
+                    .number_of_values(1)
+                    .conflicts_with_all(&["user", "api-key-file"]),
+            )
+            .arg(
+                clap::Arg::with_name("api-key-file")
+                    .long("api-key-file")
+                    .number_of_values(1)
+                    .conflicts_with("user"),
+            )
+            .arg(
+                clap::Arg::with_name("ca-cert")
+                    .long("ca-cert")
+                    .number_of_values(1),
+            )
+            .arg(
+                clap::Arg::with_name("insecure")
+                    .long("insecure")
+                    .conflicts_with("ca-cert"),
+            )
             .arg(
                 clap::Arg::with_name("browser")
                     .long("browser")
@@ -63,37 +174,153 @@ impl Cli {
                     .number_of_values(1)
                     .validator(|arg| {
                         if !arg.eq("firefox") && !arg.eq("firefox-esr") && !arg.eq("chrome") {
-                            return Err(format!(
+                            Err(format!(
                                 "browser {} not valid. Plase entry firefox, chrome or firefox-esr",
                                 arg
-                            ));
+                            ))
                         } else {
-                            return Ok(());
+                            Ok(())
                         }
                     }),
             )
+            .arg(
+                clap::Arg::with_name("shell")
+                    .long("shell")
+                    .short("s")
+                    .number_of_values(1)
+                    .possible_values(&["bash", "zsh", "fish"]),
+            )
+            .arg(
+                clap::Arg::with_name("max-output")
+                    .long("max-output")
+                    .number_of_values(1)
+                    .validator(|arg| {
+                        if check_max_output(&arg).is_none() {
+                            Err(format!("max output {} not valid", arg))
+                        } else {
+                            Ok(())
+                        }
+                    }),
+            )
+            .arg(
+                clap::Arg::with_name("redact")
+                    .long("redact")
+                    .multiple(true)
+                    .number_of_values(1)
+                    .validator(|arg| {
+                        if let Err(err) = regex::Regex::new(&arg) {
+                            Err(format!("redact regex {} not valid: {}", arg, err))
+                        } else {
+                            Ok(())
+                        }
+                    }),
+            )
+            .arg(
+                clap::Arg::with_name("include")
+                    .long("include")
+                    .multiple(true)
+                    .number_of_values(1)
+                    .validator(|arg| {
+                        if let Err(err) = Filter::check_rule(&arg) {
+                            Err(format!("include {}", err))
+                        } else {
+                            Ok(())
+                        }
+                    }),
+            )
+            .arg(
+                clap::Arg::with_name("exclude")
+                    .long("exclude")
+                    .multiple(true)
+                    .number_of_values(1)
+                    .validator(|arg| {
+                        if let Err(err) = Filter::check_rule(&arg) {
+                            Err(format!("exclude {}", err))
+                        } else {
+                            Ok(())
+                        }
+                    }),
+            )
+            .arg(
+                clap::Arg::with_name("output")
+                    .long("output")
+                    .short("o")
+                    .multiple(true)
+                    .number_of_values(1)
+                    .default_value("es")
+                    .validator(|arg| {
+                        if SinkKind::parse(&arg).is_none() {
+                            Err(format!(
+                                "output {} not valid. Please entry es, stdout or file:<path>",
+                                arg
+                            ))
+                        } else {
+                            Ok(())
+                        }
+                    }),
+            )
+            .arg(
+                clap::Arg::with_name("engagement")
+                    .long("engagement")
+                    .short("e")
+                    .number_of_values(1),
+            )
+            .arg(
+                clap::Arg::with_name("tag")
+                    .long("tag")
+                    .short("t")
+                    .multiple(true)
+                    .number_of_values(1),
+            )
             .arg(
                 clap::Arg::with_name("cfgfile")
                     .long("file")
                     .short("f")
                     .number_of_values(1)
-                    .conflicts_with_all(&["host", "port", "index", "browser"]),
+                    .conflicts_with_all(&[
+                        "host",
+                        "port",
+                        "index",
+                        "browser",
+                        "shell",
+                        "max-output",
+                        "redact",
+                        "include",
+                        "exclude",
+                        "output",
+                        "engagement",
+                        "tag",
+                        "data-stream",
+                        "ilm-policy",
+                        "scheme",
+                        "node",
+                        "cloud-id",
+                        "user",
+                        "password-env",
+                        "password-file",
+                        "api-key-env",
+                        "api-key-file",
+                        "ca-cert",
+                        "insecure",
+                    ]),
             )
-            .get_matches();
+            .get_matches_from(args);
 
         // load from cfg file
         if args.is_present("cfgfile") {
             let cfgfile = args.value_of("cfgfile").unwrap();
-            return load_cfg_file(cfgfile);
+            return load_cfg_file(cfgfile, env);
         }
 
+        // get scheme
+        let scheme = check_scheme(args.value_of("scheme").unwrap()).unwrap();
+
         // get host
-        let host: IpAddr;
         let s_host = args.value_of("host").unwrap();
-        match check_host(s_host) {
-            Some(addr) => host = addr,
+        let host = match check_host(s_host) {
+            Some(h) => h,
             None => return Err(CliError("host not valid.".to_string())),
-        }
+        };
 
         // get port
         let port: u64;
@@ -103,12 +330,50 @@ impl Cli {
             None => return Err(CliError("port not valid.".to_string())),
         }
 
+        // get nodes
+        let mut nodes: Vec<String> = Vec::new();
+        if let Some(s_nodes) = args.values_of("node") {
+            for s_node in s_nodes {
+                match check_node(s_node) {
+                    Some(n) => nodes.push(n),
+                    None => return Err(CliError(format!("node {} not valid.", s_node))),
+                }
+            }
+        }
+        let mut cloud_id: Option<String> = None;
+        if let Some(s_cloud_id) = args.value_of("cloud-id") {
+            match check_cloud_id(s_cloud_id) {
+                Some(c) => cloud_id = Some(c),
+                None => return Err(CliError("cloud id not valid.".to_string())),
+            }
+        }
+
+        // get credentials
+        let username = args.value_of("user").map(|u| u.to_owned());
+        let password =
+            read_secret(args.value_of("password-env"), args.value_of("password-file"), env)?;
+        let api_key = read_secret(args.value_of("api-key-env"), args.value_of("api-key-file"), env)?;
+        let auth = check_auth(username, password, api_key)?;
+
+        // get certificates options
+        let mut ca_cert: Option<PathBuf> = None;
+        if let Some(s_ca_cert) = args.value_of("ca-cert") {
+            match check_file(s_ca_cert) {
+                Some(path) => ca_cert = Some(path),
+                None => return Err(CliError("CA bundle not valid.".to_string())),
+            }
+        }
+        let verify_certs = !args.is_present("insecure");
+
         // get idx
         let index: String;
         match args.value_of("index") {
             Some(idx) => index = idx.to_owned(),
             None => return Err(CliError("plase specify an index name.".to_string())),
         }
+        let data_stream = args.is_present("data-stream");
+        check_index(&index, data_stream)?;
+        let ilm_policy = args.value_of("ilm-policy").map(|p| p.to_owned());
 
         let interface: String;
         match args.value_of("interface") {
@@ -126,91 +391,474 @@ impl Cli {
             }
         }
 
+        // get shell, the user's one by default
+        let shell = match args.value_of("shell") {
+            Some(s_shell) => Shell::parse(s_shell).unwrap(),
+            None => Shell::from_env().unwrap_or(Shell::Bash),
+        };
+
+        // get max output size of each command
+        let max_output = match args.value_of("max-output") {
+            Some(s_max) => check_max_output(s_max).unwrap(),
+            None => DEFAULT_MAX_OUTPUT,
+        };
+
+        // get user-defined redaction regexes
+        let redact: Vec<String> = match args.values_of("redact") {
+            Some(r) => r.map(|r| r.to_owned()).collect(),
+            None => Vec::new(),
+        };
+
+        // get include/exclude rules
+        let include: Vec<String> = match args.values_of("include") {
+            Some(r) => r.map(|r| r.to_owned()).collect(),
+            None => Vec::new(),
+        };
+        let exclude: Vec<String> = match args.values_of("exclude") {
+            Some(r) => r.map(|r| r.to_owned()).collect(),
+            None => Vec::new(),
+        };
+
+        // get outputs
+        let mut outputs: Vec<SinkKind> = Vec::new();
+        for s_output in args.values_of("output").unwrap() {
+            match SinkKind::parse(s_output) {
+                Some(o) => outputs.push(o),
+                None => return Err(CliError("output not valid.".to_string())),
+            }
+        }
+
+        // get session labels
+        let engagement = args.value_of("engagement").map(|e| e.to_owned());
+        let tags: Vec<String> = match args.values_of("tag") {
+            Some(t) => t.map(|t| t.to_owned()).collect(),
+            None => Vec::new(),
+        };
+
         Ok(Cli {
+            scheme,
             host,
             port,
+            nodes,
+            cloud_id,
+            auth,
+            ca_cert,
+            verify_certs,
             index,
+            data_stream,
+            ilm_policy,
             interface,
             browser,
+            shell,
+            max_output,
+            redact,
+            include,
+            exclude,
+            outputs,
+            engagement,
+            tags,
         })
     }
 }
 
-fn check_host(host: &str) -> Option<IpAddr> {
-    if host.eq("localhost") {
-        return Some("127.0.0.1".parse::<IpAddr>().unwrap());
+/**
+ * `tracker ctl`: a command for the running tracker, sent by `trk` from the
+ * tracked shell.
+ */
+#[cfg(not(target_os = "windows"))]
+pub struct CtlCli {
+    pub id: u32, /* TRACKER_ID of the tracker to control */
+    pub request: Request,
+}
+
+#[cfg(not(target_os = "windows"))]
+impl CtlCli {
+    pub fn new(args: &[String]) -> Result<CtlCli, CliError> {
+        let args = clap::App::new("tracker ctl")
+            .about("Control the running tracker from the tracked shell")
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .arg(
+                clap::Arg::with_name("id")
+                    .long("id")
+                    .number_of_values(1)
+                    .env("TRACKER_ID"),
+            )
+            .subcommand(clap::SubCommand::with_name("pause").about("Stop tracking"))
+            .subcommand(clap::SubCommand::with_name("resume").about("Start tracking again"))
+            .subcommand(
+                clap::SubCommand::with_name("tag")
+                    .about("Add a tag to the following records")
+                    .arg(clap::Arg::with_name("label").required(true)),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("note")
+                    .about("Record a note")
+                    .arg(clap::Arg::with_name("text").required(true).multiple(true)),
+            )
+            .subcommand(clap::SubCommand::with_name("status").about("Show the tracker status"))
+            .get_matches_from(args);
+
+        let id = match args.value_of("id").map(|i| i.parse::<u32>()) {
+            Some(Ok(id)) => id,
+            Some(Err(_)) => return Err(CliError("tracker id not valid.".to_string())),
+            None => {
+                return Err(CliError(
+                    "no tracker id: run from a tracked shell or use --id.".to_string(),
+                ))
+            }
+        };
+
+        let request = match args.subcommand() {
+            ("pause", _) => Request::Pause,
+            ("resume", _) => Request::Resume,
+            ("tag", Some(sub)) => Request::Tag(sub.value_of("label").unwrap().to_owned()),
+            ("note", Some(sub)) => {
+                let text: Vec<&str> = sub.values_of("text").unwrap().collect();
+                Request::Note(text.join(" "))
+            }
+            _ => Request::Status,
+        };
+
+        Ok(CtlCli { id, request })
     }
-    return match host.parse::<IpAddr>() {
-        Ok(addr) => Some(addr),
-        Err(_) => None,
-    };
 }
 
-fn check_port(port: &str) -> Option<u64> {
-    return match port.parse::<u64>() {
-        Ok(p) => Some(p),
-        Err(_) => None,
-    };
+/**
+ * `tracker attach`: register a shell to the running `tracker daemon`, run by
+ * the shell hooks. With `--exec`, tracker registers itself and becomes the
+ * shell, e.g. as the default command of tmux.
+ */
+#[cfg(not(target_os = "windows"))]
+pub struct AttachCli {
+    pub pid: u32,           /* pid of the shell */
+    pub exec: Option<Shell>, /* shell to become, if any */
 }
 
-fn check_browser(browser: &str) -> Option<Browser> {
-    return match browser {
-        "chrome" => Some(Browser::Chrome),
-        "firefox" => Some(Browser::Firefox),
-        "firefox-esr" => Some(Browser::FirefoxEsr),
+#[cfg(not(target_os = "windows"))]
+impl AttachCli {
+    pub fn new(args: &[String]) -> Result<AttachCli, CliError> {
+        let args = clap::App::new("tracker attach")
+            .about("Attach a shell to tracker daemon")
+            .arg(clap::Arg::with_name("pid").required_unless("exec"))
+            .arg(
+                clap::Arg::with_name("exec")
+                    .long("exec")
+                    .conflicts_with("pid")
+                    .help("Run the shell attached, or untracked if the daemon isn't running"),
+            )
+            .arg(
+                clap::Arg::with_name("shell")
+                    .long("shell")
+                    .number_of_values(1)
+                    .possible_values(&["bash", "zsh", "fish"])
+                    .requires("exec"),
+            )
+            .get_matches_from(args);
+
+        if args.is_present("exec") {
+            let shell = match args.value_of("shell") {
+                Some(s_shell) => Shell::parse(s_shell).unwrap(),
+                None => Shell::from_env().unwrap_or(Shell::Bash),
+            };
+            return Ok(AttachCli {
+                pid: std::process::id(),
+                exec: Some(shell),
+            });
+        }
+
+        let pid = args
+            .value_of("pid")
+            .unwrap()
+            .parse::<u32>()
+            .map_err(|_| CliError("shell pid not valid.".to_string()))?;
+
+        Ok(AttachCli { pid, exec: None })
+    }
+}
+
+/**
+ * `tracker nest`: become a shell recording to the tracked session given,
+ * run through `tracker wrap` for shells started in the tracked shell.
+ */
+#[cfg(not(target_os = "windows"))]
+pub struct NestCli {
+    pub nesting: Nesting,
+    pub shell: Shell,         /* shell to become */
+    pub options: Vec<String>, /* its options */
+}
+
+#[cfg(not(target_os = "windows"))]
+impl NestCli {
+    pub fn new(args: &[String]) -> Result<NestCli, CliError> {
+        let value = |name: &'static str| clap::Arg::with_name(name).long(name).number_of_values(1);
+        let args = clap::App::new("tracker nest")
+            .about("Run a shell nested in a tracked session")
+            .arg(value("id").required(true))
+            .arg(value("session-dir").required(true))
+            .arg(value("depth").required(true))
+            .arg(value("home").required(true))
+            .arg(value("pty"))
+            .arg(value("shell").possible_values(&["bash", "zsh", "fish"]))
+            .arg(clap::Arg::with_name("options").multiple(true).last(true))
+            .get_matches_from(args);
+
+        let id = args
+            .value_of("id")
+            .unwrap()
+            .parse::<u32>()
+            .map_err(|_| CliError("session id not valid.".to_string()))?;
+        let depth = args
+            .value_of("depth")
+            .unwrap()
+            .parse::<u32>()
+            .map_err(|_| CliError("nesting depth not valid.".to_string()))?;
+        let shell = match args.value_of("shell") {
+            Some(s_shell) => Shell::parse(s_shell).unwrap(),
+            None => Shell::from_env().unwrap_or(Shell::Bash),
+        };
+        let options = args
+            .values_of("options")
+            .map(|o| o.map(|o| o.to_owned()).collect())
+            .unwrap_or_default();
+
+        Ok(NestCli {
+            nesting: Nesting {
+                id,
+                session_dir: PathBuf::from(args.value_of("session-dir").unwrap()),
+                depth,
+                home: PathBuf::from(args.value_of("home").unwrap()),
+                pty: args.value_of("pty").map(|nonce| nonce.to_owned()),
+            },
+            shell,
+            options,
+        })
+    }
+}
+
+lazy_static! {
+    static ref RE_HOSTNAME: regex::Regex = regex::Regex::new(
+        r#"^[a-zA-Z0-9]([a-zA-Z0-9-]{0,61}[a-zA-Z0-9])?(\.[a-zA-Z0-9]([a-zA-Z0-9-]{0,61}[a-zA-Z0-9])?)*$"#
+    )
+    .unwrap();
+    static ref RE_NODE: regex::Regex =
+        regex::Regex::new(r#"^https?://(\[[0-9a-fA-F:.]+\]|[.0-9a-zA-Z-]+)(:\d+)?/?$"#).unwrap();
+    static ref RE_INTERFACE: regex::Regex = regex::Regex::new(r#"^[a-zA-Z0-9._-]+$"#).unwrap();
+}
+
+fn check_scheme(scheme: &str) -> Option<Scheme> {
+    match scheme {
+        "http" => Some(Scheme::Http),
+        "https" => Some(Scheme::Https),
         _ => None,
-    };
+    }
 }
 
-fn load_host(line: &str) -> Option<IpAddr> {
-    let re = regex::Regex::new(r#"^host:\s+([.0-9a-z]+)$"#).unwrap();
-    if let Some(host) = re.captures(line) {
-        return check_host(&host[1]);
+fn check_host(host: &str) -> Option<String> {
+    if host.parse::<IpAddr>().is_ok() || RE_HOSTNAME.is_match(host) {
+        return Some(host.to_owned());
     }
+    None
+}
 
+fn check_node(node: &str) -> Option<String> {
+    if RE_NODE.is_match(node) {
+        return Some(node.to_owned());
+    }
     None
 }
 
-fn load_port(line: &str) -> Option<u64> {
-    let re = regex::Regex::new(r#"^port:\s+(\d+)$"#).unwrap();
-    if let Some(port) = re.captures(line) {
-        return check_port(&port[1]);
+fn check_cloud_id(cloud_id: &str) -> Option<String> {
+    if is_cloud_id(cloud_id) {
+        return Some(cloud_id.to_owned());
     }
+    None
+}
 
+fn check_interface(interface: &str) -> Option<String> {
+    if RE_INTERFACE.is_match(interface) {
+        return Some(interface.to_owned());
+    }
     None
 }
 
-fn load_index(line: &str) -> Option<String> {
-    let re = regex::Regex::new(r#"^index:\s+(.*)$"#).unwrap();
-    return match re.captures(line) {
-        Some(index) => Some(index[1].to_string()),
-        None => None,
+fn check_bool(value: &str) -> Option<bool> {
+    match value {
+        "true" | "yes" => Some(true),
+        "false" | "no" => Some(false),
+        _ => None,
+    }
+}
+
+fn check_file(path: &str) -> Option<PathBuf> {
+    match path_expand(path) {
+        Ok(p) if p.is_file() => Some(p),
+        _ => None,
+    }
+}
+
+fn check_index(index: &str, data_stream: bool) -> Result<(), CliError> {
+    if is_index_pattern(index) {
+        if data_stream {
+            return Err(CliError("a data stream name cannot contain dates.".to_string()));
+        }
+        if !check_index_pattern(index) {
+            return Err(CliError(format!("index pattern {} not valid.", index)));
+        }
+    }
+    Ok(())
+}
+
+fn check_auth(
+    username: Option<String>,
+    password: Option<String>,
+    api_key: Option<String>,
+) -> Result<Option<Auth>, CliError> {
+    match (username, password, api_key) {
+        (None, None, None) => Ok(None),
+        (Some(u), Some(p), None) => Ok(Some(Auth::Basic(u, p))),
+        (None, None, Some(k)) => match k.find(':') {
+            Some(i) => Ok(Some(Auth::ApiKey(k[..i].to_owned(), k[i + 1..].to_owned()))),
+            None => Err(CliError("api key not valid: expected <id>:<key>.".to_string())),
+        },
+        (Some(_), None, None) => Err(CliError("password not present for user.".to_string())),
+        (None, Some(_), _) => Err(CliError("user not present for password.".to_string())),
+        (_, _, Some(_)) => Err(CliError("use either user/password or api key.".to_string())),
+    }
+}
+
+/**
+ * Read a secret from the environment variable `var` if set, else from the
+ * file `path` if set.
+ */
+fn read_secret(
+    var: Option<&str>,
+    path: Option<&str>,
+    env: &dyn Fn(&str) -> Option<String>,
+) -> Result<Option<String>, CliError> {
+    match (var, path) {
+        (Some(var), _) => Ok(Some(read_secret_env(var, env)?)),
+        (_, Some(path)) => Ok(Some(read_secret_file(path)?)),
+        _ => Ok(None),
+    }
+}
+
+/**
+ * Read a secret from the environment variable `var`, looked up with `env`.
+ */
+fn read_secret_env(var: &str, env: &dyn Fn(&str) -> Option<String>) -> Result<String, CliError> {
+    match env(var) {
+        Some(secret) if !secret.is_empty() => Ok(secret),
+        _ => Err(CliError(format!("environment variable {} not set.", var))),
+    }
+}
+
+/**
+ * Read a secret from the first line of file `path`.
+ */
+fn read_secret_file(path: &str) -> Result<String, CliError> {
+    let path = path_expand(path).map_err(|err| CliError(err.to_string()))?;
+    let contents = read_to_string(&path).map_err(|err| {
+        CliError(format!(
+            "failed to read secret file {}: {}.",
+            path.display(),
+            err
+        ))
+    })?;
+    match contents.lines().next() {
+        Some(secret) if !secret.is_empty() => Ok(secret.to_owned()),
+        _ => Err(CliError(format!("secret file {} is empty.", path.display()))),
+    }
+}
+
+fn check_port(port: &str) -> Option<u64> {
+    port.parse::<u64>().ok()
+}
+
+/* bytes, or kilobytes/megabytes with a k/m suffix */
+fn check_max_output(max: &str) -> Option<usize> {
+    let (digits, unit) = match max.to_lowercase().chars().last() {
+        Some('k') => (&max[..max.len() - 1], 1024),
+        Some('m') => (&max[..max.len() - 1], 1024 * 1024),
+        _ => (max, 1),
     };
+    match digits.parse::<usize>() {
+        Ok(n) if n > 0 => n.checked_mul(unit),
+        _ => None,
+    }
 }
 
-fn load_browser(line: &str) -> Option<Browser> {
-    let re = regex::Regex::new(r#"^browser:\s+(.*)$"#).unwrap();
-    if let Some(browser) = re.captures(line) {
-        return check_browser(&browser[1]);
+fn check_browser(browser: &str) -> Option<Browser> {
+    match browser {
+        "chrome" => Some(Browser::Chrome),
+        "firefox" => Some(Browser::Firefox),
+        "firefox-esr" => Some(Browser::FirefoxEsr),
+        _ => None,
     }
+}
 
-    None
+/**
+ * Load the value of a generic `key: value` line.
+ */
+fn load_value(key: &str, line: &str) -> Option<String> {
+    let re = regex::Regex::new(&format!(r#"^{}:\s+(.*)$"#, regex::escape(key))).unwrap();
+    re.captures(line).map(|value| value[1].trim_end().to_string())
 }
 
-fn load_interface(line: &str) -> Option<String> {
-    let re = regex::Regex::new(r#"^inte:\s+([a-z0-9])$"#).unwrap();
-    return match re.captures(line) {
-        Some(interface) => Some(interface[1].to_string()),
-        None => None,
+/**
+ * Load the value of the cfg file line `line` for `key`, converted by `check`.
+ * The line is left out of the error of secret keys.
+ */
+fn load_key<T, F>(key: &str, line: &str, pos: usize, check: F) -> Result<T, CliError>
+where
+    F: FnOnce(&str) -> Option<T>,
+{
+    match load_value(key, line).and_then(|value| check(&value)) {
+        Some(value) => Ok(value),
+        None if key == "password" || key == "api_key" => Err(CliError(format!(
+            "failed to read cfg file: bad {} at position {}",
+            key, pos
+        ))),
+        None => Err(CliError(format!(
+            "failed to read cfg file: bad {} at position {}: {}",
+            key, pos, line
+        ))),
     }
 }
 
-fn load_cfg_file<P: AsRef<Path>>(filepath: P) -> Result<Cli, CliError> {
-    let mut host: Option<IpAddr> = None;
+fn check_string(value: &str) -> Option<String> {
+    if value.is_empty() {
+        return None;
+    }
+    Some(value.to_owned())
+}
+
+fn load_cfg_file<P: AsRef<Path>>(
+    filepath: P,
+    env: &dyn Fn(&str) -> Option<String>,
+) -> Result<Cli, CliError> {
+    let mut scheme: Option<Scheme> = None;
+    let mut host: Option<String> = None;
     let mut port: Option<u64> = None;
+    let mut nodes: Vec<String> = Vec::new();
+    let mut cloud_id: Option<String> = None;
+    let mut username: Option<String> = None;
+    let mut password: Option<String> = None;
+    let mut api_key: Option<String> = None;
+    let mut ca_cert: Option<PathBuf> = None;
+    let mut verify_certs: Option<bool> = None;
     let mut index: Option<String> = None;
+    let mut data_stream: Option<bool> = None;
+    let mut ilm_policy: Option<String> = None;
     let mut browser: Option<Browser> = None;
     let mut interface: Option<String> = None;
+    let mut shell: Option<Shell> = None;
+    let mut max_output: Option<usize> = None;
+    let mut redact: Vec<String> = Vec::new();
+    let mut include: Vec<String> = Vec::new();
+    let mut exclude: Vec<String> = Vec::new();
+    let mut outputs: Vec<SinkKind> = Vec::new();
+    let mut engagement: Option<String> = None;
+    let mut tags: Vec<String> = Vec::new();
     let errmsg = "failed to read cfg file:";
 
     if !filepath.as_ref().is_file() {
@@ -233,68 +881,63 @@ fn load_cfg_file<P: AsRef<Path>>(filepath: P) -> Result<Cli, CliError> {
     }
 
     for (pos, line) in contents.lines().enumerate() {
-        if line == "" {
+        if line.is_empty() || line.starts_with("#") {
             continue;
-        } else if line.starts_with("#") {
-            continue;
-        } else if line.starts_with("host:") {
-            match load_host(line) {
-                Some(h) => host = Some(h),
-                None => {
-                    return Err(CliError(format!(
-                        "{} bad host at position {}: {}",
-                        errmsg, pos, line
-                    )))
-                }
-            }
-        } else if line.starts_with("port:") {
-            match load_port(line) {
-                Some(p) => port = Some(p),
-                None => {
-                    return Err(CliError(format!(
-                        "{} bad port at position {}: {}",
-                        errmsg, pos, line
-                    )))
-                }
+        }
+
+        let key = match line.find(':') {
+            Some(i) => &line[..i],
+            None => return Err(CliError(format!("bad line at position {}: {}", pos, line))),
+        };
+        match key {
+            "scheme" => scheme = Some(load_key(key, line, pos, check_scheme)?),
+            "host" => host = Some(load_key(key, line, pos, check_host)?),
+            "port" => port = Some(load_key(key, line, pos, check_port)?),
+            "node" => nodes.push(load_key(key, line, pos, check_node)?),
+            "cloud_id" => cloud_id = Some(load_key(key, line, pos, check_cloud_id)?),
+            "username" => username = Some(load_key(key, line, pos, check_string)?),
+            "password" => password = Some(load_key(key, line, pos, check_string)?),
+            "password_env" => {
+                password = Some(read_secret_env(&load_key(key, line, pos, check_string)?, env)?)
             }
-        } else if line.starts_with("index:") {
-            match load_index(line) {
-                Some(idx) => index = Some(idx),
-                None => {
-                    return Err(CliError(format!(
-                        "{} bad index at position {}: {}",
-                        errmsg, pos, line
-                    )))
-                }
+            "password_file" => {
+                password = Some(read_secret_file(&load_key(key, line, pos, check_string)?)?)
             }
-        } else if line.starts_with("browser:") {
-            match load_browser(line) {
-                Some(b) => browser = Some(b),
-                None => {
-                    return Err(CliError(format!(
-                        "{} bad browser at position {}: {}",
-                        errmsg, pos, line
-                    )))
-                }
+            "api_key" => api_key = Some(load_key(key, line, pos, check_string)?),
+            "api_key_env" => {
+                api_key = Some(read_secret_env(&load_key(key, line, pos, check_string)?, env)?)
             }
-        } else if line.starts_with("interface:") {
-            match load_interface(line) {
-                Some(i) => interface = Some(i),
-                None => {
-                    return Err(CliError(format!(
-                        "{} bad interface at position {}: {}",
-                        errmsg, pos, line
-                    )))
-                }
+            "api_key_file" => {
+                api_key = Some(read_secret_file(&load_key(key, line, pos, check_string)?)?)
             }
-        } else {
-            return Err(CliError(format!("bad line at position {}: {}", pos, line)));
+            "ca_cert" => ca_cert = Some(load_key(key, line, pos, check_file)?),
+            "verify_certs" => verify_certs = Some(load_key(key, line, pos, check_bool)?),
+            "index" => index = Some(load_key(key, line, pos, check_string)?),
+            "data_stream" => data_stream = Some(load_key(key, line, pos, check_bool)?),
+            "ilm_policy" => ilm_policy = Some(load_key(key, line, pos, check_string)?),
+            "interface" => interface = Some(load_key(key, line, pos, check_interface)?),
+            "browser" => browser = Some(load_key(key, line, pos, check_browser)?),
+            "shell" => shell = Some(load_key(key, line, pos, Shell::parse)?),
+            "max_output" => max_output = Some(load_key(key, line, pos, check_max_output)?),
+            "redact" => redact.push(load_key(key, line, pos, |v| {
+                regex::Regex::new(v).ok().map(|_| v.to_owned())
+            })?),
+            "include" => include.push(load_key(key, line, pos, |v| {
+                Filter::check_rule(v).ok().map(|_| v.to_owned())
+            })?),
+            "exclude" => exclude.push(load_key(key, line, pos, |v| {
+                Filter::check_rule(v).ok().map(|_| v.to_owned())
+            })?),
+            "output" => outputs.push(load_key(key, line, pos, SinkKind::parse)?),
+            "engagement" => engagement = Some(load_key(key, line, pos, check_string)?),
+            "tag" => tags.push(load_key(key, line, pos, check_string)?),
+            _ => return Err(CliError(format!("bad line at position {}: {}", pos, line))),
         }
     }
 
     if host.is_none() {
         // return Err(CliError(format!("{} host not present", errmsg)))
-        host = Some("127.0.0.1".parse::<IpAddr>().unwrap());
+        host = Some("127.0.0.1".to_owned());
     }
 
     if port.is_none() {
@@ -305,21 +948,159 @@ fn load_cfg_file<P: AsRef<Path>>(filepath: P) -> Result<Cli, CliError> {
     if index.is_none() {
         return Err(CliError(format!("{} index not present", errmsg)));
     }
+    let data_stream = data_stream.unwrap_or(false);
+    check_index(index.as_ref().unwrap(), data_stream)
+        .map_err(|err| CliError(format!("{} {}", errmsg, err)))?;
 
     if interface.is_none() {
         return Err(CliError(format!("{} interface not present", errmsg)));   
     }
 
+    if outputs.is_empty() {
+        outputs.push(SinkKind::Elastic);
+    }
+
+    if cloud_id.is_some() && !nodes.is_empty() {
+        return Err(CliError(format!("{} use either node or cloud_id", errmsg)));
+    }
+
+    let auth = check_auth(username, password, api_key)
+        .map_err(|err| CliError(format!("{} {}", errmsg, err)))?;
+
     // if browser.is_none() {
     //     browser = Some(Browser::FirefoxEsr);
     //     // return Err(CliError(format!("{} browser not present", errmsg)))
     // }
 
     Ok(Cli {
+        scheme: scheme.unwrap_or(Scheme::Http),
         host: host.unwrap(),
         port: port.unwrap(),
+        nodes,
+        cloud_id,
+        auth,
+        ca_cert,
+        verify_certs: verify_certs.unwrap_or(true),
         index: index.unwrap(),
+        data_stream,
+        ilm_policy,
         interface: interface.unwrap(),
         browser: browser,
+        shell: shell.unwrap_or_else(|| Shell::from_env().unwrap_or(Shell::Bash)),
+        max_output: max_output.unwrap_or(DEFAULT_MAX_OUTPUT),
+        redact,
+        include,
+        exclude,
+        outputs,
+        engagement,
+        tags,
     })
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+
+    /* es.example.com$abcd$efgh */
+    const CLOUD_ID: &str = "tracker:ZXMuZXhhbXBsZS5jb20kYWJjZCRlZmdo";
+
+    fn cli(options: &[&str]) -> Result<Cli, CliError> {
+        let mut args = vec!["tracker".to_owned(), "tracker".to_owned(), "eth0".to_owned()];
+        args.extend(options.iter().map(|o| o.to_string()));
+        Cli::parse(&args, &env)
+    }
+
+    fn cfg_file(contents: &str) -> Result<Cli, CliError> {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(file.path(), format!("index: tracker\ninterface: eth0\n{}", contents)).unwrap();
+        load_cfg_file(file.path(), &env)
+    }
+
+    /* the environment seen by the tests */
+    fn env(var: &str) -> Option<String> {
+        match var {
+            "TRACKER_TEST_PASSWORD" => Some("s3cret".to_owned()),
+            "TRACKER_TEST_API_KEY" => Some("id:key".to_owned()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn cloud_ids() {
+        assert_eq!(cli(&["--cloud-id", CLOUD_ID]).unwrap().cloud_id.unwrap(), CLOUD_ID);
+        let cfg = cfg_file(&format!("cloud_id: {}\n", CLOUD_ID)).unwrap();
+        assert_eq!(cfg.cloud_id.unwrap(), CLOUD_ID);
+
+        for bad in &["tracker", "tracker:", "tracker:not base64!", "tracker:Zm9v"] {
+            assert!(!is_cloud_id(bad), "{}", bad);
+            assert!(cli(&["--cloud-id", bad]).is_err(), "{}", bad);
+            assert!(cfg_file(&format!("cloud_id: {}\n", bad)).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn hosts_and_nodes() {
+        for host in &["localhost", "es-1.example.com", "192.0.2.7", "::1"] {
+            assert_eq!(cli(&["--host", host]).unwrap().host, *host);
+            assert_eq!(cfg_file(&format!("host: {}\n", host)).unwrap().host, *host);
+        }
+        for host in &["es_1.example.com", "es-", "http://es", "es..example.com"] {
+            assert!(cli(&["--host", host]).is_err(), "{}", host);
+            assert!(cfg_file(&format!("host: {}\n", host)).is_err(), "{}", host);
+        }
+
+        let cli_nodes = cli(&["--node", "https://es-1:9200", "--node", "http://[::1]:9200/"]);
+        assert_eq!(cli_nodes.unwrap().nodes, vec!["https://es-1:9200", "http://[::1]:9200/"]);
+        for node in &["es-1:9200", "ftp://es-1", "https://es-1:port", "https://es-1/path"] {
+            assert!(cli(&["--node", node]).is_err(), "{}", node);
+            let err = cfg_file(&format!("node: {}\n", node)).unwrap_err();
+            let expected =
+                format!("failed to read cfg file: bad node at position 2: node: {}", node);
+            assert_eq!(err.0, expected);
+        }
+    }
+
+    #[test]
+    fn secrets() {
+        let dir = tempfile::tempdir().unwrap();
+        let password_file = dir.path().join("password");
+        fs::write(&password_file, "s3cret\nignored\n").unwrap();
+        let api_key_file = dir.path().join("api_key");
+        fs::write(&api_key_file, "id:key\n").unwrap();
+        let empty_file = dir.path().join("empty");
+        fs::write(&empty_file, "").unwrap();
+
+        let password_file = password_file.to_str().unwrap();
+        let api_key_file = api_key_file.to_str().unwrap();
+        let empty_file = empty_file.to_str().unwrap();
+        let basic = |cli: Cli| match cli.auth {
+            Some(Auth::Basic(user, password)) => user == "elastic" && password == "s3cret",
+            _ => false,
+        };
+        let api_key = |cli: Cli| match cli.auth {
+            Some(Auth::ApiKey(id, key)) => id == "id" && key == "key",
+            _ => false,
+        };
+
+        assert!(basic(cli(&["-u", "elastic", "--password-env", "TRACKER_TEST_PASSWORD"]).unwrap()));
+        assert!(basic(cli(&["-u", "elastic", "--password-file", password_file]).unwrap()));
+        assert!(api_key(cli(&["--api-key-env", "TRACKER_TEST_API_KEY"]).unwrap()));
+        assert!(api_key(cli(&["--api-key-file", api_key_file]).unwrap()));
+        let cfg = cfg_file("username: elastic\npassword_env: TRACKER_TEST_PASSWORD\n");
+        assert!(basic(cfg.unwrap()));
+        let cfg = cfg_file(&format!("username: elastic\npassword_file: {}\n", password_file));
+        assert!(basic(cfg.unwrap()));
+        assert!(api_key(cfg_file("api_key_env: TRACKER_TEST_API_KEY\n").unwrap()));
+        assert!(api_key(cfg_file(&format!("api_key_file: {}\n", api_key_file)).unwrap()));
+
+        assert!(cli(&["-u", "elastic", "--password-env", "TRACKER_TEST_UNSET"]).is_err());
+        assert!(cli(&["-u", "elastic", "--password-file", empty_file]).is_err());
+        assert!(cli(&["--api-key-env", "TRACKER_TEST_PASSWORD"]).is_err());
+        assert!(cfg_file("password_env: TRACKER_TEST_PASSWORD\n").is_err());
+
+        /* secrets stay out of the errors */
+        let err = cfg_file("password:  \n").unwrap_err();
+        assert_eq!(err.0, "failed to read cfg file: bad password at position 2");
+    }
+}
@@ -1,10 +1,13 @@
+use super::output::{OutputBuffer, OutputInfo};
 use super::*;
 use regex::Regex;
 use std::fs::File;
+use std::path::Path;
 
 #[derive(Clone, Debug)]
 pub enum ErrorKind {
     BadLine(usize, String),
+    BadRecord(usize, String, String),
     OutfileNotAvailable(String, String),
 }
 
@@ -26,6 +29,9 @@ impl std::fmt::Display for ParsingError {
             ErrorKind::BadLine(pos, line) => {
                 format!("bad line at position {} : {}.", pos, line)
             },
+            ErrorKind::BadRecord(pos, line, err) => {
+                format!("bad record at position {} ({}) : {}.", pos, err, line)
+            },
             ErrorKind::OutfileNotAvailable(outfile, err) => {
                 format!("output file {} not available: {}.", outfile, err)
             }
@@ -39,12 +45,68 @@ impl std::error::Error for ParsingError {}
 type Result<T> = std::result::Result<T, ParsingError>;
 
 lazy_static! {
-    static ref RE: Regex =
-        Regex::new(r#"status="(\d+)"\suser="(.*)"\stimestamp="(\d+)"\scmd="(.*)"\soutfile="(.*)""#)
-            .unwrap();
+    /* legacy key="value" format, with or without the fields added later */
+    static ref RE: Regex = Regex::new(concat!(
+        r#"^status="(\d+)"\suser="(.*?)"\stimestamp="(\d+)"\s"#,
+        r#"(?:start="(\d+)"\send="(\d+)"\sduration="(-?\d+)"\spid="(\d+)"\sshlvl="(\d+)"\s"#,
+        r#"tty="(.*?)"\scwd="(.*)"\s)?cmd="(.*)"\soutfile="(.*)"$"#
+    ))
+    .unwrap();
+}
+
+/**
+ * A histlog line as written by the shell hooks: one JSON object per line.
+ */
+#[derive(Debug, serde::Deserialize)]
+struct HistRecord {
+    status: u64,
+    #[serde(default)]
+    user: String,
+    timestamp: u64,
+    #[serde(default)]
+    start: u64,
+    #[serde(default)]
+    end: u64,
+    #[serde(default)]
+    duration: i64, /* may be negative if the clock went back */
+    #[serde(default)]
+    pid: u64,
+    #[serde(default)]
+    shlvl: u64,
+    #[serde(default)]
+    depth: u64, /* nesting in the tracked shell, through sudo, su or a shell */
+    #[serde(default)]
+    tty: String,
+    #[serde(default)]
+    cwd: String,
+    cmd: String,
+    #[serde(default)]
+    outfile: String, /* empty if the shell can't capture the output */
+    #[serde(default)]
+    mux: String, /* "tmux", "screen" or empty */
+    #[serde(default)]
+    mux_session: String,
+    #[serde(default)]
+    mux_window: String,
+    #[serde(default)]
+    mux_pane: String,
+    #[serde(default)]
+    host_name: String, /* remote host, empty if run on the tracker host */
+    #[serde(default)]
+    host_ip: String,
+    #[serde(default)]
+    host_os: String, /* `uname -s` */
+    #[serde(default)]
+    host_kernel: String,
+}
+
+fn parse_json(n: usize, line: &str) -> Result<HistRecord> {
+    serde_json::from_str(line).map_err(|err| {
+        ParsingError::new(ErrorKind::BadRecord(n, line.to_owned(), err.to_string()))
+    })
 }
 
-fn parse_line(n: usize, line: &str) -> Result<ConsoleHistEntry> {
+fn parse_legacy(n: usize, line: &str) -> Result<HistRecord> {
     let captures: regex::Captures;
     match RE.captures(line) {
         Some(c) => captures = c,
@@ -53,53 +115,474 @@ fn parse_line(n: usize, line: &str) -> Result<ConsoleHistEntry> {
         ),
     }
 
-    let status = captures[1].parse::<u64>().unwrap();
-    let user = captures[2].to_string();
-    let timestamp = captures[3].parse::<u64>().unwrap();
-    let cmd = captures[4].to_string();
-    let outfile = captures[5].to_string();
-
-    let output: String;
-    match File::open(&outfile) {
-        Ok(mut file) => {
-            let mut buf = Vec::new();
-            match file.read_to_end(&mut buf) {
-                Ok(_) => output = String::from_utf8_lossy(&buf).to_string(),
-                Err(err) => {
-                    return Err(ParsingError::new(ErrorKind::OutfileNotAvailable(
-                        outfile,
-                        err.to_string(),
-                    )))
+    let number = |i: usize| captures.get(i).map_or(0, |m| m.as_str().parse::<u64>().unwrap_or(0));
+    let text = |i: usize| captures.get(i).map_or("", |m| m.as_str()).replace("\\\"", "\"");
+
+    Ok(HistRecord {
+        status: number(1),
+        user: text(2),
+        timestamp: number(3),
+        start: number(4),
+        end: number(5),
+        duration: captures.get(6).map_or(0, |m| m.as_str().parse::<i64>().unwrap_or(0)),
+        pid: number(7),
+        shlvl: number(8),
+        depth: 0,
+        tty: text(9),
+        cwd: text(10),
+        cmd: text(11),
+        outfile: text(12),
+        mux: String::new(),
+        mux_session: String::new(),
+        mux_window: String::new(),
+        mux_pane: String::new(),
+        host_name: String::new(),
+        host_ip: String::new(),
+        host_os: String::new(),
+        host_kernel: String::new(),
+    })
+}
+
+/* how long the output of a command may take to be stored, in milliseconds */
+const OUTPUT_WAIT: u64 = 5000;
+
+fn read_output(outfile: &str) -> Result<String> {
+    let mut buf = Vec::new();
+    File::open(outfile)
+        .and_then(|mut file| file.read_to_end(&mut buf))
+        .map_err(|err| {
+            ParsingError::new(ErrorKind::OutfileNotAvailable(
+                outfile.to_owned(),
+                err.to_string(),
+            ))
+        })?;
+    Ok(String::from_utf8_lossy(&buf).to_string())
+}
+
+/**
+ * Output of `record`, plain and raw, with its info. The pty already limits
+ * the output it stores and describes it in `<outfile>.meta`; an output
+ * stored by the shell itself is limited to `max_output` bytes here.
+ */
+fn load_output(record: &HistRecord, max_output: usize) -> Result<(String, String, OutputInfo)> {
+    if record.outfile.is_empty() {
+        return Ok((String::new(), String::new(), OutputInfo::default()));
+    }
+
+    if let Ok(meta) = std::fs::read(format!("{}.meta", record.outfile)) {
+        let info: OutputInfo = serde_json::from_slice(&meta).unwrap_or_default();
+        let output = read_output(&record.outfile)?;
+        let output_raw = read_output(&format!("{}.raw", record.outfile)).unwrap_or_else(|_| output.clone());
+        return Ok((output, output_raw, info));
+    }
+
+    let mut buffer = OutputBuffer::new(max_output);
+    File::open(&record.outfile)
+        .and_then(|mut file| {
+            let mut buf = [0u8; 8192];
+            loop {
+                let n = file.read(&mut buf)?;
+                if n == 0 {
+                    return Ok(());
                 }
+                buffer.push(&buf[..n]);
             }
-        }
-        Err(err) => {
-            return Err(ParsingError::new(ErrorKind::OutfileNotAvailable(
-                outfile,
+        })
+        .map_err(|err| {
+            ParsingError::new(ErrorKind::OutfileNotAvailable(
+                record.outfile.clone(),
                 err.to_string(),
-            )))
-        }
-    };
-
-    Ok(ConsoleHistEntry {
-        user,
-        status,
-        timestamp,
-        cmd,
-        output,
-    })
+            ))
+        })?;
+    let (raw, output, info) = buffer.finish();
+    Ok((output, String::from_utf8_lossy(&raw).to_string(), info))
 }
 
-pub fn parse_histfile_contents(contents: &str) -> Result<Vec<ConsoleHistEntry>> {
+/**
+ * The output of `record` is still being stored by the pty.
+ */
+fn output_pending(record: &HistRecord) -> bool {
+    if record.outfile.is_empty() || Path::new(&record.outfile).exists() {
+        return false;
+    }
+    let end = if record.end > 0 { record.end } else { record.timestamp * 1000 };
+    timestamp_now_ms() < end + OUTPUT_WAIT
+}
+
+fn parse_line(n: usize, line: &str) -> Result<HistRecord> {
+    if line.trim_start().starts_with('{') {
+        parse_json(n, line)
+    } else {
+        parse_legacy(n, line)
+    }
+}
+
+/**
+ * Parse the histlog lines in `contents`.
+ * Bad lines are skipped and returned as errors along with the good records.
+ * A record whose output file can't be read is kept, with an empty output.
+ * Outputs are limited to `max_output` bytes, see `OutputBuffer`.
+ * Parsing stops at the first record whose output isn't stored yet: the
+ * number of bytes parsed is returned too.
+ */
+pub fn parse_histfile_contents(
+    contents: &[u8],
+    max_output: usize,
+) -> (Vec<ConsoleHistEntry>, Vec<ParsingError>, usize) {
     let mut records: Vec<ConsoleHistEntry> = Vec::new();
-    for (pos, line) in contents.lines().enumerate() {
-        let record = parse_line(pos, line)?;
-        records.push(record);
+    let mut errors: Vec<ParsingError> = Vec::new();
+    let mut consumed = 0;
+
+    for (pos, raw_line) in contents.split_inclusive(|&b| b == b'\n').enumerate() {
+        let line = String::from_utf8_lossy(raw_line);
+        let line = line.trim_end_matches('\n');
+        if line.trim().is_empty() {
+            consumed += raw_line.len();
+            continue;
+        }
+
+        let record = match parse_line(pos, line) {
+            Ok(record) => record,
+            Err(err) => {
+                errors.push(err);
+                consumed += raw_line.len();
+                continue;
+            }
+        };
+
+        if output_pending(&record) {
+            break;
+        }
+        consumed += raw_line.len();
+
+        let (output, output_raw, output_info) = match load_output(&record, max_output) {
+            Ok(output) => output,
+            Err(err) => {
+                errors.push(err);
+                (String::new(), String::new(), OutputInfo::default())
+            }
+        };
+
+        let mux = if record.mux.is_empty() {
+            None
+        } else {
+            Some(Multiplexer {
+                name: record.mux,
+                session: record.mux_session,
+                window: record.mux_window,
+                pane: record.mux_pane,
+            })
+        };
+
+        let host = if record.host_name.is_empty() {
+            None
+        } else {
+            Some(RemoteHost {
+                name: record.host_name,
+                ip: record.host_ip,
+                os: match record.host_os.to_lowercase().as_str() {
+                    "darwin" => "macos".to_owned(),
+                    os => os.to_owned(),
+                },
+                kernel: record.host_kernel,
+            })
+        };
+
+        records.push(ConsoleHistEntry {
+            seq: 0,
+            user: record.user,
+            status: record.status,
+            timestamp: record.timestamp,
+            start: record.start,
+            end: record.end,
+            duration: std::cmp::max(record.duration, 0) as u64,
+            pid: record.pid,
+            shlvl: record.shlvl,
+            depth: record.depth,
+            tty: record.tty,
+            cwd: record.cwd,
+            mux,
+            host,
+            cmd: record.cmd,
+            output,
+            output_raw,
+            output_info,
+        });
     }
-    Ok(records)
+
+    (records, errors, consumed)
 }
 
 #[cfg(test)]
 mod test {
-    // ...
+    use super::super::output::DEFAULT_MAX_OUTPUT;
+    use super::*;
+    use std::io::Write;
+
+    fn outfile(dir: &tempfile::TempDir, name: &str, contents: &str) -> String {
+        let path = dir.path().join(name);
+        File::create(&path).unwrap().write_all(contents.as_bytes()).unwrap();
+        path.to_str().unwrap().to_owned()
+    }
+
+    fn json_line(cmd: &str, outfile: &str) -> String {
+        serde_json::json!({
+            "status": 0, "user": "root", "timestamp": 1600000000,
+            "start": 1600000000000u64, "end": 1600000000250u64, "duration": 250,
+            "pid": 42, "shlvl": 1, "tty": "/dev/pts/0", "cwd": "/tmp",
+            "cmd": cmd, "outfile": outfile,
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn parse_json_record() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = outfile(&dir, "out_1.txt", "hello\n");
+
+        let contents = format!("{}\n", json_line("echo hello", &out));
+        let (records, errors, _) = parse_histfile_contents(contents.as_bytes(), DEFAULT_MAX_OUTPUT);
+        assert!(errors.is_empty());
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].cmd, "echo hello");
+        assert_eq!(records[0].output, "hello\n");
+        assert_eq!(records[0].duration, 250);
+        assert_eq!(records[0].pid, 42);
+        assert_eq!(records[0].tty, "/dev/pts/0");
+        assert_eq!(records[0].mux, None);
+        assert_eq!(records[0].depth, 0);
+
+        /* from a root shell started by sudo -i */
+        let mut nested: serde_json::Value = serde_json::from_str(&json_line("id", "")).unwrap();
+        nested["depth"] = serde_json::json!(1);
+        let contents = format!("{}\n", nested);
+        let (records, errors, _) = parse_histfile_contents(contents.as_bytes(), DEFAULT_MAX_OUTPUT);
+        assert!(errors.is_empty());
+        assert_eq!(records[0].depth, 1);
+    }
+
+    #[test]
+    fn parse_remote_host() {
+        let mut remote: serde_json::Value = serde_json::from_str(&json_line("id", "")).unwrap();
+        remote["host_name"] = serde_json::json!("jump");
+        remote["host_ip"] = serde_json::json!("10.0.0.7");
+        remote["host_os"] = serde_json::json!("Linux");
+        remote["host_kernel"] = serde_json::json!("5.10.0");
+
+        let contents = format!("{}\n{}\n", remote, json_line("id", ""));
+        let (records, errors, _) = parse_histfile_contents(contents.as_bytes(), DEFAULT_MAX_OUTPUT);
+        assert!(errors.is_empty());
+        assert_eq!(
+            records[0].host,
+            Some(RemoteHost {
+                name: "jump".to_owned(),
+                ip: "10.0.0.7".to_owned(),
+                os: "linux".to_owned(),
+                kernel: "5.10.0".to_owned(),
+            })
+        );
+        assert_eq!(records[1].host, None);
+    }
+
+    #[test]
+    fn parse_multiplexer() {
+        let mut tmux: serde_json::Value = serde_json::from_str(&json_line("ls", "")).unwrap();
+        tmux["mux"] = serde_json::json!("tmux");
+        tmux["mux_session"] = serde_json::json!("recon");
+        tmux["mux_window"] = serde_json::json!("2");
+        tmux["mux_pane"] = serde_json::json!("1");
+
+        let contents = format!("{}\n", tmux);
+        let (records, errors, _) = parse_histfile_contents(contents.as_bytes(), DEFAULT_MAX_OUTPUT);
+        assert!(errors.is_empty());
+        assert_eq!(
+            records[0].mux,
+            Some(Multiplexer {
+                name: "tmux".to_owned(),
+                session: "recon".to_owned(),
+                window: "2".to_owned(),
+                pane: "1".to_owned(),
+            })
+        );
+    }
+
+    #[test]
+    fn parse_json_special_commands() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = outfile(&dir, "out_1.txt", "");
+
+        let tricky = r#"echo "a" outfile="/etc/passwd""#;
+        let multiline = "for i in 1 2; do\n  echo \"$i\"\ndone";
+        let contents = format!("{}\n{}\n", json_line(tricky, &out), json_line(multiline, &out));
+
+        let (records, errors, _) = parse_histfile_contents(contents.as_bytes(), DEFAULT_MAX_OUTPUT);
+        assert!(errors.is_empty());
+        assert_eq!(records[0].cmd, tricky);
+        assert_eq!(records[1].cmd, multiline);
+    }
+
+    #[test]
+    fn parse_zsh_records() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = outfile(&dir, "out_1.txt", "\u{1b}[1mbold\u{1b}[0m\n");
+
+        /* as written by tracker.zsh: the command line comes from preexec */
+        let contents = format!(
+            concat!(
+                r#"{{"status":0,"user":"op","timestamp":1600000000,"start":1600000000000,"#,
+                r#""end":1600000000012,"duration":12,"pid":4242,"shlvl":2,"tty":"/dev/pts/3","#,
+                r#""cwd":"/home/op","cmd":"print -P '%B\u001b'\nls **/*(.)","outfile":"{}"}}"#,
+                "\n",
+                r#"{{"status":130,"user":"op","timestamp":1600000001,"start":1600000001000,"#,
+                r#""end":1600000003000,"duration":2000,"pid":4242,"shlvl":2,"tty":"/dev/pts/3","#,
+                r#""cwd":"/home/op","cmd":"sleep 10","outfile":"{}"}}"#,
+                "\n"
+            ),
+            out, out
+        );
+
+        let (records, errors, _) = parse_histfile_contents(contents.as_bytes(), DEFAULT_MAX_OUTPUT);
+        assert!(errors.is_empty());
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].cmd, "print -P '%B\u{1b}'\nls **/*(.)");
+        assert_eq!(records[0].output, "bold\n");
+        assert_eq!(records[0].output_raw, "\u{1b}[1mbold\u{1b}[0m\n");
+        assert_eq!(records[0].shlvl, 2);
+        assert_eq!(records[1].status, 130);
+        assert_eq!(records[1].duration, 2000);
+    }
+
+    #[test]
+    fn parse_fish_record() {
+        /* as written by tracker.fish: duration is $CMD_DURATION, no output file */
+        let contents = concat!(
+            r#"{"status":1,"user":"op","timestamp":1600000000,"start":1600000000000,"#,
+            r#""end":1600000001500,"duration":1500,"pid":777,"shlvl":1,"tty":"","#,
+            r#""cwd":"/home/op","cmd":"for f in *.txt\n    echo $f\nend","outfile":""}"#,
+            "\n"
+        );
+
+        let (records, errors, _) = parse_histfile_contents(contents.as_bytes(), DEFAULT_MAX_OUTPUT);
+        assert!(errors.is_empty());
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].status, 1);
+        assert_eq!(records[0].duration, 1500);
+        assert_eq!(records[0].cmd, "for f in *.txt\n    echo $f\nend");
+        assert_eq!(records[0].output, "");
+    }
+
+    #[test]
+    fn parse_legacy_records() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = outfile(&dir, "out_1.txt", "root\n");
+
+        let old = format!(r#"status="0" user="root" timestamp="1600000000" cmd="whoami" outfile="{}""#, out);
+        let new = format!(
+            concat!(
+                r#"status="1" user="root" timestamp="1600000001" start="1600000001000" "#,
+                r#"end="1600000001010" duration="10" pid="42" shlvl="2" tty="" "#,
+                r#"cwd="/tmp/\"q\"" cmd="echo \"x\"" outfile="{}""#
+            ),
+            out
+        );
+
+        let contents = format!("{}\n{}\n", old, new);
+        let (records, errors, _) = parse_histfile_contents(contents.as_bytes(), DEFAULT_MAX_OUTPUT);
+        assert!(errors.is_empty());
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].cmd, "whoami");
+        assert_eq!(records[0].output, "root\n");
+        assert_eq!(records[1].status, 1);
+        assert_eq!(records[1].shlvl, 2);
+        assert_eq!(records[1].cwd, "/tmp/\"q\"");
+        assert_eq!(records[1].cmd, "echo \"x\"");
+    }
+
+    #[test]
+    fn bad_lines_are_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = outfile(&dir, "out_1.txt", "");
+
+        let contents = format!(
+            "{}\n{{\"status\": 0, \"cmd\": \n garbage\n{}\n",
+            json_line("ls", &out),
+            json_line("pwd", &out)
+        );
+
+        let (records, errors, _) = parse_histfile_contents(contents.as_bytes(), DEFAULT_MAX_OUTPUT);
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].cmd, "ls");
+        assert_eq!(records[1].cmd, "pwd");
+        assert_eq!(errors.len(), 2);
+    }
+
+    #[test]
+    fn pending_output_is_deferred() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out_2.txt");
+        let first = outfile(&dir, "out_1.txt", "");
+
+        let mut pending: serde_json::Value = serde_json::from_str(&json_line("ls", out.to_str().unwrap())).unwrap();
+        pending["end"] = serde_json::json!(timestamp_now_ms());
+        let contents = format!("{}\n{}\n", json_line("pwd", &first), pending);
+
+        let (records, errors, consumed) = parse_histfile_contents(contents.as_bytes(), DEFAULT_MAX_OUTPUT);
+        assert!(errors.is_empty());
+        assert_eq!(records.len(), 1);
+        assert_eq!(consumed, contents.find('\n').unwrap() + 1);
+
+        /* stored by the pty */
+        outfile(&dir, "out_2.txt", "file\n");
+        outfile(&dir, "out_2.txt.raw", "\u{1b}[34mfile\u{1b}[0m\r\n");
+        outfile(&dir, "out_2.txt.meta", r#"{"bytes":16,"truncated":false,"binary":false}"#);
+        let (records, _, consumed) = parse_histfile_contents(&contents.as_bytes()[consumed..], DEFAULT_MAX_OUTPUT);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].output, "file\n");
+        assert_eq!(records[0].output_raw, "\u{1b}[34mfile\u{1b}[0m\r\n");
+        assert_eq!(consumed, contents.len() - contents.find('\n').unwrap() - 1);
+    }
+
+    #[test]
+    fn missing_outfile_keeps_record() {
+        /* ended long ago: the output won't come */
+        let contents = json_line("ls", "/nonexistent/out_1.txt");
+        let (records, errors, _) = parse_histfile_contents(contents.as_bytes(), DEFAULT_MAX_OUTPUT);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].output, "");
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn output_is_limited() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = outfile(&dir, "out_1.txt", &"x".repeat(100));
+        let contents = json_line("cat big.log", &out);
+
+        let (records, errors, _) = parse_histfile_contents(contents.as_bytes(), 20);
+        assert!(errors.is_empty());
+        let info = &records[0].output_info;
+        assert_eq!(info.bytes, 100);
+        assert!(info.truncated);
+        assert_eq!(
+            records[0].output,
+            format!("{}\n[... 80 bytes truncated ...]\n{}", "x".repeat(10), "x".repeat(10))
+        );
+    }
+
+    #[test]
+    fn output_meta_is_read() {
+        /* as stored by the pty */
+        let dir = tempfile::tempdir().unwrap();
+        let out = outfile(&dir, "out_1.txt", "");
+        outfile(&dir, "out_1.txt.raw", "");
+        outfile(&dir, "out_1.txt.meta", r#"{"bytes":2048,"truncated":false,"binary":true,"sha256":"ab12"}"#);
+        let contents = json_line("cat /bin/ls", &out);
+
+        let (records, errors, _) = parse_histfile_contents(contents.as_bytes(), DEFAULT_MAX_OUTPUT);
+        assert!(errors.is_empty());
+        let info = &records[0].output_info;
+        assert!(info.binary);
+        assert_eq!(info.bytes, 2048);
+        assert_eq!(info.sha256.as_deref(), Some("ab12"));
+    }
 }
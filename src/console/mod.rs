@@ -1,9 +1,13 @@
+use crate::filter::Filter;
+use crate::log;
 use crate::utils::*;
 
 use std::fmt;
+use std::fs::{self, File};
 use std::io::prelude::*;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::{Command, ExitStatus};
+use std::sync::Arc;
 use std::thread;
 
 #[cfg(not(target_os = "windows"))]
@@ -14,21 +18,37 @@ mod parser;
 #[path = "win_parser.rs"]
 mod parser;
 
+pub mod output;
 #[cfg(not(target_os = "windows"))]
-const TRACKER_LOGS_DIR: &str = "~/.tracker/histlogs";
+pub mod pty;
+#[cfg(not(target_os = "windows"))]
+pub mod recorder;
+
 #[cfg(target_os = "windows")]
 const TRACKER_LOGS_DIR: &str = "~\\AppData\\Local\\tracker\\histlogs";
 
 #[cfg(not(target_os = "windows"))]
-const TRACKER_INIT: &str = "~/.tracker/.tracker.rc";
+pub const TRACKER_SESSIONS_DIR: &str = "~/.tracker/sessions";
+#[cfg(target_os = "windows")]
+pub const TRACKER_SESSIONS_DIR: &str = "~\\AppData\\Local\\tracker\\sessions";
+
+/* session dirs moved aside are removed after these many days */
+const STALE_SESSION_DAYS: u64 = 7;
 
+#[cfg(not(target_os = "windows"))]
+pub const TRACKER_RECORDINGS_DIR: &str = "~/.tracker/recordings";
 #[cfg(target_os = "windows")]
-const TRACKER_INIT: &str = "~\\AppData\\Local\\tracker\\startup-files\\tracker.ps1";
+pub const TRACKER_RECORDINGS_DIR: &str = "~\\AppData\\Local\\tracker\\recordings";
 
 #[cfg(not(target_os = "windows"))]
-const SHELL: &str = "/bin/bash";
+const TRACKER_INIT: &str = "~/.tracker/.tracker.rc";
+#[cfg(not(target_os = "windows"))]
+const TRACKER_ZSH_INIT: &str = "~/.tracker/zsh/.zshrc";
+#[cfg(not(target_os = "windows"))]
+const TRACKER_FISH_INIT: &str = "~/.tracker/fish/tracker.fish";
+
 #[cfg(target_os = "windows")]
-const SHELL: &str = "powershell";
+const TRACKER_INIT: &str = "~\\AppData\\Local\\tracker\\startup-files\\tracker.ps1";
 
 #[derive(Clone, Debug)]
 pub struct ConsoleError(String);
@@ -41,15 +61,127 @@ impl fmt::Display for ConsoleError {
 
 impl std::error::Error for ConsoleError {}
 
+/**
+ * Shell started by `start_console`.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl Shell {
+    /**
+     * Parse a shell name: "bash", "zsh" or "fish".
+     */
+    pub fn parse(shell: &str) -> Option<Shell> {
+        match shell {
+            "bash" => Some(Shell::Bash),
+            "zsh" => Some(Shell::Zsh),
+            "fish" => Some(Shell::Fish),
+            _ => None,
+        }
+    }
+
+    /**
+     * Shell of the user, as set by $SHELL (e.g. "/usr/bin/zsh").
+     */
+    pub fn from_env() -> Option<Shell> {
+        Shell::from_path(std::env::var_os("SHELL")?)
+    }
+
+    /**
+     * Shell run by the program `path`, None if not supported.
+     */
+    pub fn from_path<P: AsRef<std::path::Path>>(path: P) -> Option<Shell> {
+        Shell::parse(path.as_ref().file_name()?.to_str()?)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Shell::Bash => "bash",
+            Shell::Zsh => "zsh",
+            Shell::Fish => "fish",
+        }
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    fn init_file(&self) -> &'static str {
+        match self {
+            Shell::Bash => TRACKER_INIT,
+            Shell::Zsh => TRACKER_ZSH_INIT,
+            Shell::Fish => TRACKER_FISH_INIT,
+        }
+    }
+
+    #[cfg(target_os = "windows")]
+    fn init_file(&self) -> &'static str {
+        TRACKER_INIT
+    }
+}
+
+impl fmt::Display for Shell {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/**
+ * Terminal multiplexer pane a command ran in.
+ */
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize)]
+pub struct Multiplexer {
+    pub name: String,    /* "tmux" or "screen" */
+    pub session: String, /* tmux session name, screen $STY */
+    pub window: String,  /* window index */
+    pub pane: String,    /* pane index, empty for screen */
+}
+
+/**
+ * Remote host a command ran on, in a shell started by `tracker ssh`.
+ */
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize)]
+pub struct RemoteHost {
+    pub name: String,
+    pub ip: String,     /* address ssh reached it at, empty if unknown */
+    pub os: String,     /* named as std::env::consts::OS: "linux", "macos" */
+    pub kernel: String,
+}
+
 #[derive(Debug, Clone, serde::Serialize)]
 pub struct ConsoleHistEntry {
+    pub seq: u64, /* position of the entry in the session history */
     pub timestamp: u64,
     #[cfg(not(target_os = "windows"))]
     pub user: String,
     pub cmd: String,
     #[cfg(not(target_os = "windows"))]
     pub status: u64,
-    pub output: String,
+    #[cfg(not(target_os = "windows"))]
+    pub start: u64, /* milliseconds */
+    #[cfg(not(target_os = "windows"))]
+    pub end: u64, /* milliseconds */
+    #[cfg(not(target_os = "windows"))]
+    pub duration: u64, /* milliseconds */
+    #[cfg(not(target_os = "windows"))]
+    pub pid: u64, /* shell pid */
+    #[cfg(not(target_os = "windows"))]
+    pub shlvl: u64,
+    #[cfg(not(target_os = "windows"))]
+    pub depth: u64, /* nested shells from the tracked one, sudo and su included */
+    #[cfg(not(target_os = "windows"))]
+    pub tty: String,
+    #[cfg(not(target_os = "windows"))]
+    pub cwd: String,
+    #[cfg(not(target_os = "windows"))]
+    pub mux: Option<Multiplexer>, /* tmux or screen pane, if any */
+    #[cfg(not(target_os = "windows"))]
+    pub host: Option<RemoteHost>, /* None if run on the tracker host */
+    pub output: String, /* without terminal escape sequences */
+    #[cfg(not(target_os = "windows"))]
+    pub output_raw: String, /* as written to the terminal */
+    pub output_info: output::OutputInfo, /* size, truncation and hash of the output */
 }
 
 #[derive(Clone, Debug)]
@@ -59,6 +191,12 @@ pub struct ConsoleHistControl {
     histfile: PathBuf,              /* where the history is stored */
     offset: u64,                    /* location within this history */
     length: u64,                    /* length of the histfile */
+    count: u64,                     /* entries read so far */
+    recording: Option<(PathBuf, u64)>, /* terminal recording and its start in milliseconds */
+    max_output: usize,              /* bytes of each command output kept */
+    filter: Filter,                 /* commands to track */
+    session_dir: PathBuf,           /* per-session state of the shell */
+    lock: Option<Arc<File>>,        /* keeps other trackers out of session_dir */
 }
 
 impl ConsoleHistControl {
@@ -69,19 +207,30 @@ impl ConsoleHistControl {
             histfile: PathBuf::new(),
             offset: 0,
             length: 0,
+            count: 0,
+            recording: None,
+            max_output: output::DEFAULT_MAX_OUTPUT,
+            filter: Filter::default(),
+            session_dir: PathBuf::new(),
+            lock: None,
         }
     }
 
+    /**
+     * Get ready to read the history of the shell of tracker `id`, whose
+     * state lives in its own session dir. Fails if another tracker uses `id`.
+     */
     pub fn init(&mut self, id: u32) -> Result<(), ConsoleError> {
         self.reset();
 
-        let histfile = if cfg!(not(target_os = "windows")) {
-            format!("{}/hist.{}.log", TRACKER_LOGS_DIR, id)
-        } else {
-            format!("{}/history.log", TRACKER_LOGS_DIR)
-        };
+        let dir = session_dir(id)?;
+        let lock = lock_session_dir(&dir, id)?;
+        prune_session_dirs(&dir);
 
-        let histfile = path_expand(&histfile).map_err(|err| {
+        #[cfg(not(target_os = "windows"))]
+        let histfile = dir.join("hist.log");
+        #[cfg(target_os = "windows")]
+        let histfile = path_expand(format!("{}/history.log", TRACKER_LOGS_DIR)).map_err(|err| {
             return ConsoleError(format!("histfile error: {}", err.to_string()));
         })?;
 
@@ -101,6 +250,8 @@ impl ConsoleHistControl {
         self.histfile = histfile;
         self.offset = 0;
         self.length = length;
+        self.session_dir = dir;
+        self.lock = lock.map(Arc::new);
 
         Ok(())
     }
@@ -120,16 +271,38 @@ impl ConsoleHistControl {
             return Ok(0);
         }
 
-        let contents = file_read_from(&self.histfile, self.offset)
+        let contents = file_read_bytes_from(&self.histfile, self.offset)
             .map_err(|err| ConsoleError(format!("failed to read histfile: {}", err.to_string())))?;
 
-        let history = parser::parse_histfile_contents(&contents).map_err(|err| {
-            ConsoleError(format!("failed to parse histfile: {}", err.to_string()))
-        })?;
+        /* the last line may still be being written by the shell */
+        let complete = match contents.iter().rposition(|&b| b == b'\n') {
+            Some(pos) => &contents[..=pos],
+            None => return Ok(0),
+        };
+
+        /* records whose output isn't stored yet are read again next time */
+        let (mut history, errors, consumed) = parser::parse_histfile_contents(complete, self.max_output);
+        for err in errors {
+            log::log_error(&format!(
+                "histfile {}: {}",
+                self.histfile.display(),
+                err
+            ));
+        }
+
+        #[cfg(not(target_os = "windows"))]
+        history.retain(|entry| self.filter.keep_command(&entry.cmd, &entry.cwd, entry.start));
+        #[cfg(target_os = "windows")]
+        history.retain(|entry| self.filter.keep_command(&entry.cmd, "", entry.timestamp * 1000));
+
+        for entry in &mut history {
+            entry.seq = self.count;
+            self.count += 1;
+        }
 
         let n = history.len();
         self.history.extend(history);
-        self.offset = self.length;
+        self.offset += consumed as u64;
 
         Ok(n)
     }
@@ -142,31 +315,211 @@ impl ConsoleHistControl {
         self.clear();
         self.id = None;
         self.offset = 0;
+        self.count = 0;
+        self.recording = None;
+        self.lock = None;
+    }
+
+    pub fn id(&self) -> Option<u32> {
+        self.id
     }
 
     pub fn history(&self) -> &Vec<ConsoleHistEntry> {
         &self.history
     }
+
+    pub fn max_output(&self) -> usize {
+        self.max_output
+    }
+
+    pub fn set_max_output(&mut self, max_output: usize) {
+        self.max_output = max_output;
+    }
+
+    pub fn session_dir(&self) -> &Path {
+        &self.session_dir
+    }
+
+    /**
+     * Remove the session dir once the shell is gone and its history dumped.
+     * It is kept if the histlog still has records to read, waiting for
+     * their output.
+     */
+    pub fn remove_session_dir(&mut self) -> Result<(), ConsoleError> {
+        if self.id.is_none() {
+            return Ok(());
+        }
+        #[cfg(not(target_os = "windows"))]
+        {
+            let length = fs::metadata(&self.histfile).map(|m| m.len()).unwrap_or(0);
+            if length > self.offset {
+                return Err(ConsoleError(format!(
+                    "session dir {} kept: {} bytes of histlog left",
+                    self.session_dir.display(),
+                    length - self.offset
+                )));
+            }
+        }
+
+        fs::remove_dir_all(&self.session_dir).map_err(|err| {
+            ConsoleError(format!(
+                "failed to remove session dir {}: {}",
+                self.session_dir.display(),
+                err
+            ))
+        })?;
+        self.reset();
+        Ok(())
+    }
+
+    pub fn set_filter(&mut self, filter: Filter) {
+        self.filter = filter;
+    }
+
+    pub fn set_recording(&mut self, path: PathBuf, start_ms: u64) {
+        self.recording = Some((path, start_ms));
+    }
+
+    /**
+     * Terminal recording of the history, if any, and where `entry` starts
+     * in it (seconds).
+     */
+    pub fn recording_offset(&self, entry: &ConsoleHistEntry) -> Option<(&PathBuf, f64)> {
+        let (path, start_ms) = self.recording.as_ref()?;
+        #[cfg(not(target_os = "windows"))]
+        let offset = entry.start.saturating_sub(*start_ms) as f64 / 1000.0;
+        #[cfg(target_os = "windows")]
+        let offset = (entry.timestamp * 1000).saturating_sub(*start_ms) as f64 / 1000.0;
+        Some((path, offset))
+    }
 }
 
+/**
+ * Directory of the per-session state of tracker `id`: histlog, outputs,
+ * pause flag and control socket.
+ */
+pub fn session_dir(id: u32) -> Result<PathBuf, ConsoleError> {
+    path_expand(format!("{}/{}", TRACKER_SESSIONS_DIR, id))
+        .map_err(|err| ConsoleError(format!("session dir error: {}", err)))
+}
 
 /**
- * Start a shell (bash/powershell) with commands history readable by `ctrl`.
+ * Create the session dir `dir` of tracker `id` and lock it until the lock
+ * is dropped. A dir left by a session that is gone is moved aside; a dir
+ * locked by a running tracker means that `id` is already in use.
  */
-pub fn start_console(
-    id: u32,
-    ctrl: &mut ConsoleHistControl,
-) -> Result<thread::JoinHandle<ExitStatus>, ConsoleError> {
-    ctrl.init(id).map_err(|err| {
+#[cfg(not(target_os = "windows"))]
+fn lock_session_dir(dir: &Path, id: u32) -> Result<Option<File>, ConsoleError> {
+    use std::os::unix::io::AsRawFd;
+
+    loop {
+        fs::create_dir_all(dir).map_err(|err| {
+            ConsoleError(format!(
+                "failed to create session dir {}: {}",
+                dir.display(),
+                err
+            ))
+        })?;
+
+        let lock = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(dir.join("lock"))
+            .map_err(|err| ConsoleError(format!("failed to lock session dir: {}", err)))?;
+        if unsafe { libc::flock(lock.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            let err = std::io::Error::last_os_error();
+            if err.kind() == std::io::ErrorKind::WouldBlock {
+                return Err(ConsoleError(format!(
+                    "session id {} is in use by another tracker ({})",
+                    id,
+                    dir.display()
+                )));
+            }
+            return Err(ConsoleError(format!("failed to lock session dir: {}", err)));
+        }
+
+        let stale = fs::read_dir(dir)
+            .map_err(|err| ConsoleError(format!("failed to read session dir: {}", err)))?
+            .filter_map(|entry| entry.ok())
+            .any(|entry| entry.file_name() != "lock");
+        if !stale {
+            return Ok(Some(lock));
+        }
+
+        drop(lock);
+        let aside = dir.with_file_name(format!("{}.{}", id, timestamp_now_ms()));
+        fs::rename(dir, &aside).map_err(|err| {
+            ConsoleError(format!(
+                "failed to move stale session dir {}: {}",
+                dir.display(),
+                err
+            ))
+        })?;
+        log::log_info(&format!(
+            "stale session dir {} moved to {}",
+            dir.display(),
+            aside.display()
+        ));
+    }
+}
+
+/**
+ * Remove the session dirs moved aside by `lock_session_dir`, next to `dir`,
+ * more than STALE_SESSION_DAYS ago.
+ */
+fn prune_session_dirs(dir: &Path) {
+    let entries = match dir.parent().map(fs::read_dir) {
+        Some(Ok(entries)) => entries,
+        _ => return,
+    };
+    let oldest = timestamp_now_ms().saturating_sub(STALE_SESSION_DAYS * 24 * 3600 * 1000);
+
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        /* `<id>.<milliseconds>` */
+        let name = entry.file_name().to_string_lossy().to_string();
+        let moved = name
+            .split_once('.')
+            .filter(|(id, _)| id.parse::<u32>().is_ok())
+            .and_then(|(_, ms)| ms.parse::<u64>().ok());
+        if let Some(ms) = moved {
+            if ms < oldest {
+                match fs::remove_dir_all(entry.path()) {
+                    Ok(()) => log::log_info(&format!("stale session dir {} removed", entry.path().display())),
+                    Err(err) => log::log_error(&format!(
+                        "failed to remove stale session dir {}: {}",
+                        entry.path().display(),
+                        err
+                    )),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn lock_session_dir(dir: &Path, _id: u32) -> Result<Option<File>, ConsoleError> {
+    fs::create_dir_all(dir).map_err(|err| {
         ConsoleError(format!(
-            "failed to init history control: {}",
+            "failed to create session dir {}: {}",
+            dir.display(),
             err.to_string()
         ))
     })?;
+    Ok(None)
+}
 
-    let init = path_expand(TRACKER_INIT).map_err(|err| {
-        ConsoleError(format!("problem with shell init file: {}", err.to_string()))
-    })?;
+/**
+ * Tracker hooks of `shell`, those installed in `home` if set (e.g. the
+ * operator's, for a shell of another user).
+ */
+pub(crate) fn init_file(shell: Shell, home: Option<&Path>) -> Result<PathBuf, ConsoleError> {
+    let init = match home {
+        Some(home) => home.join(shell.init_file().trim_start_matches("~/")),
+        None => path_expand(shell.init_file()).map_err(|err| {
+            ConsoleError(format!("problem with shell init file: {}", err))
+        })?,
+    };
 
     if !init.as_path().exists() {
         return Err(ConsoleError(format!(
@@ -174,31 +527,162 @@ pub fn start_console(
             init.display()
         )));
     }
+    Ok(init)
+}
+
+/**
+ * Command starting `shell` with the tracker hooks loaded, see `init_file`.
+ */
+pub(crate) fn shell_command(shell: Shell, home: Option<&Path>) -> Result<Command, ConsoleError> {
+    let init = init_file(shell, home)?;
 
-    let args = if cfg!(target_os = "windows") {
-        vec![
+    let mut command: Command;
+    if cfg!(target_os = "windows") {
+        command = Command::new("powershell");
+        command.args(&[
             "-noexit".to_owned(),
             "-command".to_owned(),
             format!(". {}", init.as_path().to_str().unwrap()),
-        ]
+        ]);
     } else {
-        vec![
-            "--rcfile".to_owned(),
-            init.as_path().to_str().unwrap().to_owned(),
-        ]
+        command = Command::new(shell.name());
+        match shell {
+            Shell::Bash => {
+                command.arg("--rcfile").arg(&init);
+            }
+            Shell::Zsh => {
+                /* zsh reads its .zshrc from ZDOTDIR: the tracker one sources the user's */
+                command.arg("-i").env("ZDOTDIR", init.parent().unwrap());
+                if let Some(zdotdir) = std::env::var_os("ZDOTDIR") {
+                    command.env("TRACKER_USER_ZDOTDIR", zdotdir);
+                }
+            }
+            Shell::Fish => {
+                /* sourced after the user's config.fish and conf.d */
+                let path = init.to_str().unwrap().replace('\\', "\\\\").replace('\'', "\\'");
+                command.arg("-i").arg("--init-command").arg(format!("source '{}'", path));
+            }
+        }
+    }
+    /* `trk` runs `tracker ctl` */
+    if let Ok(bin) = std::env::current_exe() {
+        command.env("TRACKER_BIN", bin);
+    }
+
+    Ok(command)
+}
+
+/**
+ * Replace the current process with `shell`, attached to tracker daemon as
+ * `id` (the pid stays the same), or a plain `shell` if `id` is None.
+ * Returns only on error.
+ */
+#[cfg(not(target_os = "windows"))]
+pub fn exec_shell(shell: Shell, id: Option<u32>) -> ConsoleError {
+    use std::os::unix::process::CommandExt;
+
+    let mut command = match id {
+        Some(id) => {
+            let mut command = match shell_command(shell, None) {
+                Ok(command) => command,
+                Err(err) => return err,
+            };
+            let dir = match session_dir(id) {
+                Ok(dir) => dir,
+                Err(err) => return err,
+            };
+            command.env("TRACKER_ID", id.to_string());
+            command.env("TRACKER_ATTACHED", id.to_string());
+            command.env("TRACKER_SESSION_DIR", dir);
+            command.env_remove("TRACKER_PTY");
+            command
+        }
+        None => Command::new(shell.name()),
     };
 
-    // println!("started {} with init file {}", SHELL, init.display());
+    let err = command.exec();
+    ConsoleError(format!("failed to run {}: {}", shell, err))
+}
 
+/**
+ * Start a shell (bash/zsh/fish/powershell) with commands history readable by `ctrl`.
+ * The terminal is recorded to `recording`, if any.
+ */
+pub fn start_console(
+    id: u32,
+    shell: Shell,
+    recording: Option<PathBuf>,
+    ctrl: &mut ConsoleHistControl,
+) -> Result<thread::JoinHandle<Result<ExitStatus, ConsoleError>>, ConsoleError> {
+    ctrl.init(id).map_err(|err| {
+        ConsoleError(format!(
+            "failed to init history control: {}",
+            err
+        ))
+    })?;
+
+    let mut command = shell_command(shell, None)?;
+    command.env("TRACKER_ID", id.to_string());
+    command.env("TRACKER_SESSION_DIR", ctrl.session_dir());
+    /* tracked by this tracker, not by the daemon of a parent shell */
+    command.env_remove("TRACKER_ATTACHED");
+
+    /* the shell runs on a pty owned by tracker, which records its output */
+    #[cfg(not(target_os = "windows"))]
+    let thread = {
+        let nonce = pty::nonce();
+        command.env("TRACKER_PTY", "1");
+        command.env("TRACKER_NONCE", &nonce);
+        let pty = pty::Pty::open()?;
+
+        /* the session goes on without recording if it can't be created */
+        let mut recorder = recording.and_then(|path| {
+            match recorder::Recorder::create(&path, pty::terminal_size()) {
+                Ok(r) => Some(r),
+                Err(err) => {
+                    log::log_error(&err.to_string());
+                    None
+                }
+            }
+        });
+        if let Some(r) = recorder.as_mut() {
+            r.set_pause_file(ctrl.filter.pause_file().map(|p| p.to_path_buf()));
+            ctrl.set_recording(r.path().to_path_buf(), r.start_ms());
+        }
+
+        let capture = pty::Capture::new(ctrl.max_output(), ctrl.session_dir(), &nonce);
+        thread::spawn(move || {
+            pty.run(command, capture, recorder)
+                .map_err(|err| ConsoleError(format!("failed to run {}: {}", shell, err)))
+        })
+    };
+
+    #[cfg(target_os = "windows")]
+    let _ = recording;
+    #[cfg(target_os = "windows")]
     let thread = thread::spawn(move || {
-        Command::new(SHELL)
-            .args(&args)
-            .env("TRACKER_ID", id.to_string())
+        command
             .spawn()
-            .expect(&format!("failed to run {}", SHELL))
-            .wait()
-            .expect("failed to wait console")
+            .and_then(|mut child| child.wait())
+            .map_err(|err| ConsoleError(format!("failed to run {}: {}", shell, err.to_string())))
     });
 
     Ok(thread)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn shell_from_path() {
+        assert_eq!(Shell::from_path("/usr/bin/zsh"), Some(Shell::Zsh));
+        assert_eq!(Shell::from_path("/bin/bash"), Some(Shell::Bash));
+        assert_eq!(Shell::from_path("/usr/local/bin/fish"), Some(Shell::Fish));
+        assert_eq!(Shell::from_path("bash"), Some(Shell::Bash));
+        /* the default then, bash */
+        assert_eq!(Shell::from_path("/bin/sh"), None);
+        assert_eq!(Shell::from_path("/bin/"), None);
+        assert_eq!(Shell::from_path(""), None);
+    }
+}
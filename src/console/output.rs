@@ -0,0 +1,257 @@
+use sha2::{Digest, Sha256};
+
+use std::collections::VecDeque;
+
+pub const DEFAULT_MAX_OUTPUT: usize = 64 * 1024; /* bytes */
+
+const BINARY_SAMPLE: usize = 8192; /* bytes checked by the binary detection */
+const BINARY_RATIO: usize = 10; /* % of non-text bytes of a binary output */
+
+/**
+ * Output of a command, as stored in its record.
+ */
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct OutputInfo {
+    pub bytes: u64,             /* size of the whole output */
+    pub truncated: bool,        /* only the head and tail are kept */
+    pub binary: bool,           /* not text: only the hash is kept */
+    pub sha256: Option<String>, /* hash of the whole output, if binary */
+}
+
+/**
+ * Bounded copy of a command output: keeps its first and last `max / 2`
+ * bytes, with its size and hash.
+ */
+pub struct OutputBuffer {
+    max: usize,
+    head: Vec<u8>,
+    tail: VecDeque<u8>,
+    bytes: u64,
+    hasher: Sha256,
+}
+
+impl OutputBuffer {
+    pub fn new(max: usize) -> OutputBuffer {
+        OutputBuffer {
+            max,
+            head: Vec::new(),
+            tail: VecDeque::new(),
+            bytes: 0,
+            hasher: Sha256::new(),
+        }
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        self.bytes += data.len() as u64;
+        self.hasher.update(data);
+
+        let head_max = self.max - self.max / 2;
+        let n = std::cmp::min(head_max.saturating_sub(self.head.len()), data.len());
+        self.head.extend_from_slice(&data[..n]);
+
+        let tail_max = self.max / 2;
+        self.tail.extend(&data[n..]);
+        let excess = self.tail.len().saturating_sub(tail_max);
+        self.tail.drain(..excess);
+    }
+
+    /**
+     * Output to store, raw and as plain text, with a marker where it was
+     * truncated, and its info. A binary output is replaced by its hash.
+     */
+    pub fn finish(self) -> (Vec<u8>, String, OutputInfo) {
+        let dropped = self.bytes - (self.head.len() + self.tail.len()) as u64;
+        let mut info = OutputInfo {
+            bytes: self.bytes,
+            truncated: dropped > 0,
+            binary: is_binary(&self.head[..std::cmp::min(self.head.len(), BINARY_SAMPLE)]),
+            sha256: None,
+        };
+
+        if info.binary {
+            info.truncated = false;
+            info.sha256 = Some(format!("{:x}", self.hasher.finalize()));
+            return (Vec::new(), String::new(), info);
+        }
+
+        let tail: Vec<u8> = self.tail.into_iter().collect();
+        let mut raw = self.head.clone();
+        let mut text = strip_escapes(&self.head);
+        if dropped > 0 {
+            let marker = format!("\n[... {} bytes truncated ...]\n", dropped);
+            raw.extend_from_slice(marker.as_bytes());
+            text.push_str(&marker);
+        }
+        raw.extend_from_slice(&tail);
+        text.push_str(&strip_escapes(&tail));
+        (raw, text, info)
+    }
+}
+
+/**
+ * `sample` doesn't look like terminal text: it has NUL bytes or too many
+ * bytes that are neither utf-8 nor usual control characters.
+ */
+pub fn is_binary(sample: &[u8]) -> bool {
+    if sample.contains(&0) {
+        return true;
+    }
+
+    let mut odd = 0;
+    let mut rest = sample;
+    while !rest.is_empty() {
+        let (valid, next) = match std::str::from_utf8(rest) {
+            Ok(s) => (s, &rest[rest.len()..]),
+            Err(err) => {
+                /* an incomplete sequence at the end is fine */
+                let skip = err.error_len().unwrap_or(rest.len() - err.valid_up_to());
+                if err.error_len().is_some() {
+                    odd += skip;
+                }
+                (
+                    std::str::from_utf8(&rest[..err.valid_up_to()]).unwrap(),
+                    &rest[err.valid_up_to() + skip..],
+                )
+            }
+        };
+        odd += valid
+            .bytes()
+            .filter(|&b| (b < 0x20 && !b"\t\n\r\x07\x08\x0c\x1b".contains(&b)) || b == 0x7f)
+            .count();
+        rest = next;
+    }
+
+    odd * 100 > sample.len() * BINARY_RATIO
+}
+
+/**
+ * Plain text of terminal output `raw`: escape sequences are removed and
+ * carriage returns and backspaces applied.
+ */
+pub fn strip_escapes(raw: &[u8]) -> String {
+    let mut text: Vec<u8> = Vec::with_capacity(raw.len());
+    let mut i = 0;
+
+    while i < raw.len() {
+        let b = raw[i];
+        i += 1;
+        match b {
+            0x1b => {
+                match raw.get(i) {
+                    /* CSI: parameters, then a final byte */
+                    Some(b'[') => {
+                        i += 1;
+                        while i < raw.len() && !(0x40..=0x7e).contains(&raw[i]) {
+                            i += 1;
+                        }
+                        i += 1;
+                    }
+                    /* OSC, DCS, SOS, PM, APC: up to BEL or ST */
+                    Some(b']') | Some(b'P') | Some(b'X') | Some(b'^') | Some(b'_') => {
+                        i += 1;
+                        while i < raw.len() {
+                            if raw[i] == 0x07 {
+                                i += 1;
+                                break;
+                            }
+                            if raw[i] == 0x1b && raw.get(i + 1) == Some(&b'\\') {
+                                i += 2;
+                                break;
+                            }
+                            i += 1;
+                        }
+                    }
+                    /* character set designation */
+                    Some(b'(') | Some(b')') | Some(b'*') | Some(b'+') => i += 2,
+                    Some(_) => i += 1,
+                    None => {}
+                }
+            }
+            b'\r' => {
+                if raw.get(i) != Some(&b'\n') {
+                    /* back to the beginning of the line */
+                    let start = text.iter().rposition(|&c| c == b'\n').map_or(0, |p| p + 1);
+                    text.truncate(start);
+                }
+            }
+            0x08 => {
+                /* remove the last character, utf-8 continuation bytes included */
+                while let Some(c) = text.pop() {
+                    if c == b'\n' {
+                        text.push(c);
+                        break;
+                    }
+                    if c & 0xc0 != 0x80 {
+                        break;
+                    }
+                }
+            }
+            b'\n' | b'\t' => text.push(b),
+            0x00..=0x1f | 0x7f => {}
+            _ => text.push(b),
+        }
+    }
+
+    String::from_utf8_lossy(&text).to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn strip_escapes_sequences() {
+        let raw = b"\x1b[1;31mred\x1b[0m \x1b]0;title\x07plain\x1b(B\r\n";
+        assert_eq!(strip_escapes(raw), "red plain\n");
+    }
+
+    #[test]
+    fn strip_escapes_progress() {
+        let raw = b"10%\r50%\r100%\ndone\x08\x08\x08\x08ok\n";
+        assert_eq!(strip_escapes(raw), "100%\nok\n");
+    }
+
+    #[test]
+    fn small_output_is_kept() {
+        let mut buffer = OutputBuffer::new(64);
+        buffer.push(b"\x1b[1mhello\x1b[0m\r\n");
+        let (raw, text, info) = buffer.finish();
+        assert_eq!(raw, b"\x1b[1mhello\x1b[0m\r\n");
+        assert_eq!(text, "hello\n");
+        assert_eq!(info.bytes, 15);
+        assert!(!info.truncated && !info.binary);
+    }
+
+    #[test]
+    fn large_output_keeps_head_and_tail() {
+        let mut buffer = OutputBuffer::new(8);
+        buffer.push(b"0123456789");
+        buffer.push(b"abcdefghij");
+        let (raw, text, info) = buffer.finish();
+        assert_eq!(raw, b"0123\n[... 12 bytes truncated ...]\nghij".to_vec());
+        assert_eq!(text, "0123\n[... 12 bytes truncated ...]\nghij");
+        assert_eq!(info.bytes, 20);
+        assert!(info.truncated);
+    }
+
+    #[test]
+    fn binary_output_is_hashed() {
+        let mut buffer = OutputBuffer::new(1024);
+        buffer.push(b"\x7fELF\x02\x01\x01\x00\x00\x00");
+        let (raw, text, info) = buffer.finish();
+        assert!(raw.is_empty() && text.is_empty());
+        assert!(info.binary);
+        assert_eq!(info.bytes, 10);
+        assert_eq!(
+            info.sha256.unwrap(),
+            format!("{:x}", Sha256::digest(b"\x7fELF\x02\x01\x01\x00\x00\x00"))
+        );
+    }
+
+    #[test]
+    fn text_is_not_binary() {
+        assert!(!is_binary("\x1b[1mgrüße\x1b[0m\r\n\tok".as_bytes()));
+        assert!(!is_binary(b"split \xc3"));
+        assert!(is_binary(b"\xff\xfe\xfd\xfc text"));
+    }
+}
@@ -0,0 +1,682 @@
+use super::output::{OutputBuffer, OutputInfo};
+use super::recorder::Recorder;
+use super::ConsoleError;
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, prelude::*};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+/* OSC sequence written by the shell hooks at the command boundaries:
+ * "\x1b]6973;S;<nonce>\x07" when a command starts,
+ * "\x1b]6973;E;<nonce>;<outfile name>\x07" when it ends, before the prompt,
+ * "\x1b]6973;R;<nonce>;<record>\x07" instead in a remote shell of `tracker ssh`,
+ * which first asks its nonce with "\x1b]6973;N\x07", answered once on its
+ * input: the nonce never shows on the remote command line.
+ * Anything printed to the terminal can look like a marker: only those with
+ * the nonce of the session come from the hooks. A remote host knows its
+ * nonce, so its records are checked and it can't name outfiles. */
+const MARKER: &[u8] = b"\x1b]6973;";
+const MARKER_END: u8 = 0x07;
+const MARKER_MAX: usize = 64 << 10; /* longest marker payload: records carry the command line */
+const POLL_TIMEOUT: i32 = 100; /* milliseconds */
+
+static WINCH: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_sigwinch(_: libc::c_int) {
+    WINCH.store(true, Ordering::SeqCst);
+}
+
+fn last_error(msg: &str) -> ConsoleError {
+    ConsoleError(format!("{}: {}", msg, io::Error::last_os_error()))
+}
+
+fn window_size(fd: RawFd) -> Option<libc::winsize> {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut size) } == -1 {
+        return None;
+    }
+    Some(size)
+}
+
+/**
+ * Size (columns, rows) of the user's terminal, if any.
+ */
+pub fn terminal_size() -> Option<(u16, u16)> {
+    window_size(libc::STDIN_FILENO).map(|size| (size.ws_col, size.ws_row))
+}
+
+/* write `data` to the user's terminal and to the recording */
+fn show(data: &[u8], recorder: &mut Option<Recorder>) {
+    if data.is_empty() {
+        return;
+    }
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    let _ = stdout.write_all(data).and_then(|_| stdout.flush());
+    if let Some(recorder) = recorder {
+        recorder.output(data);
+    }
+}
+
+/**
+ * Terminal attributes of `fd`, restored when dropped.
+ */
+struct RawMode {
+    fd: RawFd,
+    termios: libc::termios,
+}
+
+impl RawMode {
+    /**
+     * Put the terminal `fd` in raw mode, keys are then sent as typed to the
+     * shell. None if `fd` isn't a terminal.
+     */
+    fn enable(fd: RawFd) -> Option<RawMode> {
+        if unsafe { libc::isatty(fd) } != 1 {
+            return None;
+        }
+
+        let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(fd, &mut termios) } == -1 {
+            return None;
+        }
+
+        let mut raw = termios;
+        unsafe {
+            libc::cfmakeraw(&mut raw);
+            libc::tcsetattr(fd, libc::TCSANOW, &raw);
+        }
+        Some(RawMode { fd, termios })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe {
+            libc::tcsetattr(self.fd, libc::TCSANOW, &self.termios);
+        }
+    }
+}
+
+/**
+ * Pseudo-terminal pair: the shell runs on the slave side while tracker
+ * proxies the user's terminal to the master side.
+ */
+pub struct Pty {
+    master: File,
+    slave: File,
+}
+
+impl Pty {
+    pub fn open() -> Result<Pty, ConsoleError> {
+        let mut master: libc::c_int = -1;
+        let mut slave: libc::c_int = -1;
+
+        /* same settings and size of the user's terminal, if any */
+        let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+        let termios_ptr = if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut termios) } == 0 {
+            &mut termios as *mut libc::termios
+        } else {
+            std::ptr::null_mut()
+        };
+        let mut size = window_size(libc::STDIN_FILENO);
+        let size_ptr = match size.as_mut() {
+            Some(s) => s as *mut libc::winsize,
+            None => std::ptr::null_mut(),
+        };
+
+        let ret = unsafe {
+            libc::openpty(&mut master, &mut slave, std::ptr::null_mut(), termios_ptr as _, size_ptr as _)
+        };
+        if ret == -1 {
+            return Err(last_error("failed to open pty"));
+        }
+
+        /* the shell must only get the slave side */
+        unsafe {
+            libc::fcntl(master, libc::F_SETFD, libc::FD_CLOEXEC);
+        }
+
+        Ok(Pty {
+            master: unsafe { File::from_raw_fd(master) },
+            slave: unsafe { File::from_raw_fd(slave) },
+        })
+    }
+
+    /**
+     * Run `command` on the slave side, as a session leader with the pty as
+     * controlling terminal, and proxy stdin/stdout to it until it exits.
+     * The output is passed to `capture` as well, and to `recorder` if any.
+     */
+    pub fn run(
+        self,
+        mut command: Command,
+        mut capture: Capture,
+        mut recorder: Option<Recorder>,
+    ) -> Result<ExitStatus, ConsoleError> {
+        let stdio = |slave: &File| -> Result<Stdio, ConsoleError> {
+            slave
+                .try_clone()
+                .map(Stdio::from)
+                .map_err(|err| ConsoleError(format!("failed to dup pty: {}", err)))
+        };
+        command
+            .stdin(stdio(&self.slave)?)
+            .stdout(stdio(&self.slave)?)
+            .stderr(stdio(&self.slave)?);
+        unsafe {
+            command.pre_exec(|| {
+                if libc::setsid() == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                if libc::ioctl(0, libc::TIOCSCTTY as _, 0) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+
+        let raw_mode = RawMode::enable(libc::STDIN_FILENO);
+        unsafe {
+            libc::signal(libc::SIGWINCH, on_sigwinch as *const () as libc::sighandler_t);
+        }
+
+        let mut child = command
+            .spawn()
+            .map_err(|err| ConsoleError(format!("failed to run shell: {}", err)))?;
+        /* reading the master fails with EIO once the shell side is closed */
+        drop(self.slave);
+
+        let mut input = self
+            .master
+            .try_clone()
+            .map_err(|err| ConsoleError(format!("failed to dup pty: {}", err)))?;
+        /* not joined: blocks on stdin until tracker exits */
+        thread::spawn(move || {
+            let mut buf = [0u8; 4096];
+            let stdin = io::stdin();
+            let mut stdin = stdin.lock();
+            loop {
+                match stdin.read(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => {
+                        if input.write_all(&buf[..n]).is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+
+        /* background jobs may keep the pty open after the shell exits */
+        let done = Arc::new(AtomicBool::new(false));
+        let async_done = Arc::clone(&done);
+
+        let mut master = self.master;
+        let output = thread::spawn(move || {
+            let mut buf = [0u8; 4096];
+            loop {
+                if WINCH.swap(false, Ordering::SeqCst) {
+                    if let Some(size) = window_size(libc::STDIN_FILENO) {
+                        unsafe {
+                            libc::ioctl(master.as_raw_fd(), libc::TIOCSWINSZ, &size);
+                        }
+                        if let Some(recorder) = recorder.as_mut() {
+                            recorder.resize(size.ws_col, size.ws_row);
+                        }
+                    }
+                }
+
+                let mut pollfd = libc::pollfd {
+                    fd: master.as_raw_fd(),
+                    events: libc::POLLIN,
+                    revents: 0,
+                };
+                let ready = unsafe { libc::poll(&mut pollfd, 1, POLL_TIMEOUT) };
+                if ready == 0 {
+                    if async_done.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    show(&capture.idle(), &mut recorder);
+                    continue;
+                }
+                if ready == -1 {
+                    if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+                        continue;
+                    }
+                    break;
+                }
+
+                match master.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => {
+                        show(&capture.feed(&buf[..n]), &mut recorder);
+                        if let Some(answer) = capture.answer.take() {
+                            let _ = master.write_all(&answer);
+                        }
+                    }
+                    Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(_) => break,
+                }
+            }
+            show(&capture.idle(), &mut recorder);
+        });
+
+        let status = child
+            .wait()
+            .map_err(|err| ConsoleError(format!("failed to wait shell: {}", err)));
+        done.store(true, Ordering::SeqCst);
+        let _ = output.join();
+        drop(raw_mode);
+        status
+    }
+}
+
+/**
+ * Random secret of the markers of a session, passed to its shells in
+ * TRACKER_NONCE.
+ */
+pub fn nonce() -> String {
+    uuid::Uuid::new_v4().to_simple().to_string()
+}
+
+/* name of an outfile in the outputs dir, without any path */
+fn is_outfile_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && !name.contains("..") && !name.contains(&['/', '\0'][..])
+}
+
+/**
+ * Split the shell output at the command boundaries and store the output of
+ * each command in the outputs dir of the session, in the outfile named by
+ * the end marker: raw in `<outfile>.raw`, without terminal escape sequences
+ * in `<outfile>`, its size and hash in `<outfile>.meta`. At most
+ * `max_output` bytes are kept.
+ */
+pub struct Capture {
+    pending: Vec<u8>,             /* data that may be the beginning of a marker */
+    output: Option<OutputBuffer>, /* output of the running command, if any */
+    max_output: usize,
+    session_dir: PathBuf,         /* with the outputs dir */
+    nonce: String,                /* carried by the markers of the hooks */
+    remote: bool,                 /* the records come from a remote shell */
+    asked: bool,                  /* the remote shell asked its nonce */
+    answer: Option<Vec<u8>>,      /* to write to the input of the shell */
+}
+
+impl Capture {
+    pub fn new(max_output: usize, session_dir: &Path, nonce: &str) -> Capture {
+        Capture {
+            pending: Vec::new(),
+            output: None,
+            max_output,
+            session_dir: session_dir.to_path_buf(),
+            nonce: nonce.to_owned(),
+            remote: false,
+            asked: false,
+            answer: None,
+        }
+    }
+
+    /**
+     * Store the records sent by a remote shell in the session dir, with
+     * their output, and answer its first ask for the nonce.
+     */
+    pub fn with_remote(mut self) -> Capture {
+        self.remote = true;
+        self
+    }
+
+    /**
+     * Process `data` read from the shell, return what should be shown to
+     * the user: everything but the markers.
+     */
+    pub fn feed(&mut self, data: &[u8]) -> Vec<u8> {
+        self.pending.extend_from_slice(data);
+        let mut shown = Vec::with_capacity(self.pending.len());
+
+        loop {
+            match find(&self.pending, MARKER) {
+                Some(i) => {
+                    self.data(&mut shown, i);
+                    let payload_start = MARKER.len();
+                    match self.pending[payload_start..].iter().position(|&b| b == MARKER_END) {
+                        Some(j) => {
+                            let payload = self.pending[payload_start..payload_start + j].to_vec();
+                            self.pending.drain(..payload_start + j + 1);
+                            self.marker(&payload);
+                        }
+                        None if self.pending.len() > MARKER_MAX => {
+                            /* not one of ours */
+                            self.data(&mut shown, MARKER.len());
+                        }
+                        None => break,
+                    }
+                }
+                None => {
+                    /* keep a possible partial marker at the end */
+                    let keep = (1..MARKER.len())
+                        .rev()
+                        .find(|&k| self.pending.ends_with(&MARKER[..k]))
+                        .unwrap_or(0);
+                    let n = self.pending.len() - keep;
+                    self.data(&mut shown, n);
+                    break;
+                }
+            }
+        }
+        shown
+    }
+
+    /**
+     * Nothing was read for a while: a partial marker was just data.
+     */
+    pub fn idle(&mut self) -> Vec<u8> {
+        let mut shown = Vec::new();
+        let n = self.pending.len();
+        self.data(&mut shown, n);
+        shown
+    }
+
+    /* move the first `n` pending bytes to `shown`, and to the output */
+    fn data(&mut self, shown: &mut Vec<u8>, n: usize) {
+        let data: Vec<u8> = self.pending.drain(..n).collect();
+        if let Some(output) = self.output.as_mut() {
+            output.push(&data);
+        }
+        shown.extend_from_slice(&data);
+    }
+
+    /* `<nonce>;<rest>` of a marker payload, None if the nonce is not ours */
+    fn signed<'a>(&self, payload: &'a [u8]) -> Option<&'a [u8]> {
+        let rest = payload.strip_prefix(self.nonce.as_bytes())?;
+        match rest.split_first() {
+            None => Some(rest),
+            Some((b';', rest)) => Some(rest),
+            Some(_) => None,
+        }
+    }
+
+    /* the outfile named by the end marker `end`, in the outputs dir */
+    fn outfile(&self, end: &[u8]) -> Option<PathBuf> {
+        let name = std::str::from_utf8(self.signed(end)?).ok()?;
+        if !is_outfile_name(name) {
+            return None;
+        }
+        Some(self.session_dir.join("outputs").join(name))
+    }
+
+    /* markers that are not ours are dropped, and the capture goes on. Any
+     * later ask for the nonce comes from the remote output */
+    fn marker(&mut self, payload: &[u8]) {
+        if payload == b"N" && self.remote && !self.asked {
+            self.asked = true;
+            self.answer = Some(format!("{}\n", self.nonce).into_bytes());
+        } else if let Some(start) = payload.strip_prefix(b"S;") {
+            if self.signed(start) == Some(b"") {
+                self.output = Some(OutputBuffer::new(self.max_output));
+            }
+        } else if let (Some(end), false) = (payload.strip_prefix(b"E;"), self.remote) {
+            let outfile = match self.outfile(end) {
+                Some(outfile) => outfile,
+                None => return,
+            };
+            let output = self
+                .output
+                .take()
+                .unwrap_or_else(|| OutputBuffer::new(self.max_output));
+            if let Err(err) = store_output(&outfile, output) {
+                crate::log::log_error(&format!(
+                    "failed to store output {}: {}",
+                    outfile.display(),
+                    err
+                ));
+            }
+        } else if let (Some(record), true) = (payload.strip_prefix(b"R;"), self.remote) {
+            let record = match self.signed(record) {
+                Some(record) => record,
+                None => return,
+            };
+            let output = self
+                .output
+                .take()
+                .unwrap_or_else(|| OutputBuffer::new(self.max_output));
+            if let Err(err) = store_remote(&self.session_dir, record, output) {
+                crate::log::log_error(&format!("failed to store remote record: {}", err));
+            }
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+/* the plain text outfile is renamed last: the parser waits for it. The
+ * outputs are not redacted yet, only the operator may read them */
+fn store_output(outfile: &Path, output: OutputBuffer) -> io::Result<()> {
+    let write = |path: &Path, contents: &[u8]| -> io::Result<()> {
+        let tmp = with_suffix(path, ".tmp");
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&tmp)?
+            .write_all(contents)?;
+        fs::rename(&tmp, path)
+    };
+
+    let (raw, text, info): (Vec<u8>, String, OutputInfo) = output.finish();
+    let meta = serde_json::to_vec(&info).map_err(|err| io::Error::other(err.to_string()))?;
+    write(&with_suffix(outfile, ".meta"), &meta)?;
+    write(&with_suffix(outfile, ".raw"), &raw)?;
+    write(outfile, text.as_bytes())
+}
+
+/* the fields of the records of the remote hooks, the first ones required */
+const REMOTE_FIELDS: &[(&str, FieldKind)] = &[
+    ("status", FieldKind::Unsigned),
+    ("timestamp", FieldKind::Unsigned),
+    ("cmd", FieldKind::String),
+    ("user", FieldKind::String),
+    ("start", FieldKind::Unsigned),
+    ("end", FieldKind::Unsigned),
+    ("duration", FieldKind::Signed),
+    ("pid", FieldKind::Unsigned),
+    ("shlvl", FieldKind::Unsigned),
+    ("depth", FieldKind::Unsigned),
+    ("tty", FieldKind::String),
+    ("cwd", FieldKind::String),
+    ("outfile", FieldKind::String),
+    ("mux", FieldKind::String),
+    ("mux_session", FieldKind::String),
+    ("mux_window", FieldKind::String),
+    ("mux_pane", FieldKind::String),
+    ("host_name", FieldKind::String),
+    ("host_ip", FieldKind::String),
+    ("host_os", FieldKind::String),
+    ("host_kernel", FieldKind::String),
+];
+const REMOTE_REQUIRED: usize = 3;
+
+#[derive(Clone, Copy)]
+enum FieldKind {
+    Unsigned,
+    Signed,
+    String,
+}
+
+/**
+ * Check a record sent by a remote host: the remote shell is not trusted, it
+ * may only set the fields of the hooks, with their types. Anything else,
+ * such as the session, tracker or user fields, refuses the record.
+ */
+fn remote_record(record: &[u8]) -> io::Result<serde_json::Map<String, serde_json::Value>> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+
+    let record: serde_json::Value =
+        serde_json::from_slice(record).map_err(|err| invalid(err.to_string()))?;
+    let record = match record {
+        serde_json::Value::Object(record) => record,
+        _ => return Err(invalid("record is not an object".to_string())),
+    };
+    for (key, value) in &record {
+        let kind = REMOTE_FIELDS
+            .iter()
+            .find(|(field, _)| *field == key.as_str())
+            .map(|&(_, kind)| kind)
+            .ok_or_else(|| invalid(format!("field {} not allowed", key)))?;
+        let valid = match kind {
+            FieldKind::Unsigned => value.is_u64(),
+            FieldKind::Signed => value.is_i64(),
+            FieldKind::String => value.is_string(),
+        };
+        if !valid {
+            return Err(invalid(format!("field {} not valid: {}", key, value)));
+        }
+    }
+    if let Some((field, _)) = REMOTE_FIELDS[..REMOTE_REQUIRED]
+        .iter()
+        .find(|(field, _)| !record.contains_key(*field))
+    {
+        return Err(invalid(format!("field {} missing", field)));
+    }
+    Ok(record)
+}
+
+/* a record of a remote shell goes to the session histlog, unless paused */
+fn store_remote(dir: &Path, record: &[u8], output: OutputBuffer) -> io::Result<()> {
+    if dir.join("paused").exists() {
+        return Ok(());
+    }
+    let mut record = remote_record(record)?;
+
+    let outputs = dir.join("outputs");
+    fs::create_dir_all(&outputs)?;
+    let outfile = outputs.join(format!("out_{}.txt", uuid::Uuid::new_v4().to_simple()));
+    store_output(&outfile, output)?;
+    record.insert("outfile".to_string(), serde_json::json!(outfile.display().to_string()));
+
+    /* one write per record: the local shells append to the histlog too */
+    let mut line = serde_json::Value::Object(record).to_string().into_bytes();
+    line.push(b'\n');
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join("hist.log"))?
+        .write_all(&line)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn capture_splits_commands() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("outputs")).unwrap();
+        let outfile = dir.path().join("outputs").join("out_1.txt");
+
+        let mut capture = Capture::new(1024, dir.path(), "f00d");
+        let mut shown = capture.feed(b"$ ls\r\n\x1b]69");
+        shown.extend(capture.feed(b"73;S;f00d\x07\x1b[34mdir\x1b[0m\r\nerr\r\n"));
+        shown.extend(capture.feed(b"\x1b]6973;E;f00d;out_1.txt\x07"));
+        shown.extend(capture.feed(b"$ "));
+
+        assert_eq!(shown, b"$ ls\r\n\x1b[34mdir\x1b[0m\r\nerr\r\n$ ");
+        assert_eq!(fs::read_to_string(&outfile).unwrap(), "dir\nerr\n");
+        let mut raw = outfile.as_os_str().to_owned();
+        raw.push(".raw");
+        assert_eq!(fs::read(&raw).unwrap(), b"\x1b[34mdir\x1b[0m\r\nerr\r\n");
+        let mode = fs::metadata(&raw).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn forged_markers_ignored() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("outputs")).unwrap();
+        let target = dir.path().join("PWNED");
+
+        let mut capture = Capture::new(1024, dir.path(), "f00d");
+        capture.feed(b"\x1b]6973;S;f00d\x07$ cat file\r\n");
+        /* printed by the command: no nonce, a wrong one, or a path */
+        let forged = format!(
+            "\x1b]6973;S\x07\x1b]6973;S;bad\x07pwned\r\n\x1b]6973;E;{0}\x07\
+             \x1b]6973;E;bad;PWNED\x07\x1b]6973;E;f00dd;PWNED\x07\x1b]6973;E;f00d;../PWNED\x07\
+             \x1b]6973;E;f00d;{0}\x07\x1b]6973;E;f00d;..\x07\x1b]6973;E;f00d;\x07",
+            target.display()
+        );
+        let shown = capture.feed(forged.as_bytes());
+        assert_eq!(shown, b"pwned\r\n");
+        assert!(!target.exists());
+        assert_eq!(fs::read_dir(dir.path().join("outputs")).unwrap().count(), 0);
+
+        /* the output of the command is still captured, whole */
+        capture.feed(b"\x1b]6973;E;f00d;out_1.txt\x07");
+        let output = fs::read_to_string(dir.path().join("outputs").join("out_1.txt")).unwrap();
+        assert_eq!(output, "$ cat file\npwned\n");
+    }
+
+    #[test]
+    fn nonce_answered_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut local = Capture::new(1024, dir.path(), "f00d");
+        local.feed(b"\x1b]6973;N\x07");
+        assert_eq!(local.answer, None);
+
+        let mut capture = Capture::new(1024, dir.path(), "f00d").with_remote();
+        assert_eq!(capture.feed(b"login\r\n\x1b]6973;N\x07$ "), b"login\r\n$ ");
+        assert_eq!(capture.answer.take(), Some(b"f00d\n".to_vec()));
+        /* printed by a remote command */
+        capture.feed(b"\x1b]6973;N\x07");
+        assert_eq!(capture.answer, None);
+    }
+
+    #[test]
+    fn remote_records_checked() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut capture = Capture::new(1024, dir.path(), "f00d").with_remote();
+        let valid = r#"{"status":0,"timestamp":1600000000,"cmd":"ls","user":"op","duration":-1}"#;
+        let hostile = [
+            r#"{"status":0,"timestamp":1600000000,"cmd":"ls","tracker.session.id":"x"}"#,
+            r#"{"status":0,"timestamp":1600000000,"cmd":"ls","user.name":"root"}"#,
+            r#"{"status":0,"timestamp":1600000000,"cmd":"ls","session":"x"}"#,
+            r#"{"status":"0","timestamp":1600000000,"cmd":"ls"}"#,
+            r#"{"status":0,"timestamp":1600000000,"cmd":["ls"]}"#,
+            r#"{"status":0,"timestamp":1600000000}"#,
+            r#"["ls"]"#,
+        ];
+        for record in &hostile {
+            capture.feed(format!("\x1b]6973;R;f00d;{}\x07", record).as_bytes());
+        }
+        /* unsigned, and end markers naming outfiles */
+        capture.feed(format!("\x1b]6973;R;{}\x07", valid).as_bytes());
+        capture.feed(b"\x1b]6973;S;f00d\x07pwned\r\n\x1b]6973;E;f00d;out_1.txt\x07");
+        assert!(!dir.path().join("hist.log").exists());
+        assert!(!dir.path().join("outputs").join("out_1.txt").exists());
+
+        capture.feed(format!("\x1b]6973;R;f00d;{}\x07", valid).as_bytes());
+        let histlog = fs::read_to_string(dir.path().join("hist.log")).unwrap();
+        assert_eq!(histlog.lines().count(), 1);
+        let record: serde_json::Value = serde_json::from_str(&histlog).unwrap();
+        assert_eq!(record["cmd"], "ls");
+        let outfile = record["outfile"].as_str().unwrap();
+        assert!(outfile.starts_with(dir.path().join("outputs").to_str().unwrap()));
+        assert_eq!(fs::read_to_string(outfile).unwrap(), "pwned\n");
+    }
+}
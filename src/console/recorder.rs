@@ -0,0 +1,205 @@
+use super::ConsoleError;
+use crate::utils::*;
+
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+const DEFAULT_SIZE: (u16, u16) = (80, 24);
+
+/**
+ * Terminal recording of a session in asciicast v2 format
+ * (https://docs.asciinema.org/manual/asciicast/v2/), replayable with
+ * `asciinema play`.
+ */
+#[derive(Debug)]
+pub struct Recorder {
+    file: File,
+    path: PathBuf,
+    start: Instant,   /* time of the header, events are relative to it */
+    start_ms: u64,    /* the same, in milliseconds since the epoch */
+    partial: Vec<u8>, /* utf-8 sequence split across two writes */
+    pause_file: Option<PathBuf>, /* nothing is recorded while it exists */
+    paused: bool,
+}
+
+impl Recorder {
+    /**
+     * Create the recording `path` for a terminal of `size` (columns, rows).
+     */
+    pub fn create<P: AsRef<Path>>(path: P, size: Option<(u16, u16)>) -> Result<Recorder, ConsoleError> {
+        let path = path_expand(&path)
+            .map_err(|err| ConsoleError(format!("recording error: {}", err)))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|err| {
+                ConsoleError(format!(
+                    "failed to create recordings dir {}: {}",
+                    dir.display(),
+                    err
+                ))
+            })?;
+        }
+
+        let mut file = File::create(&path).map_err(|err| {
+            ConsoleError(format!(
+                "failed to create recording {}: {}",
+                path.display(),
+                err
+            ))
+        })?;
+
+        let (width, height) = size.unwrap_or(DEFAULT_SIZE);
+        let start_ms = timestamp_now_ms();
+        let header = serde_json::json!({
+            "version": 2,
+            "width": width,
+            "height": height,
+            "timestamp": start_ms / 1000,
+            "env": {
+                "SHELL": std::env::var("SHELL").unwrap_or_default(),
+                "TERM": std::env::var("TERM").unwrap_or_default(),
+            },
+        });
+        writeln!(file, "{}", header).map_err(|err| {
+            ConsoleError(format!(
+                "failed to write recording {}: {}",
+                path.display(),
+                err
+            ))
+        })?;
+
+        Ok(Recorder {
+            file,
+            path,
+            start: Instant::now(),
+            start_ms,
+            partial: Vec::new(),
+            pause_file: None,
+            paused: false,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /**
+     * Start of the recording, in milliseconds since the epoch.
+     */
+    pub fn start_ms(&self) -> u64 {
+        self.start_ms
+    }
+
+    /**
+     * Stop recording while `path` exists, see `Filter::pause_file`.
+     */
+    pub fn set_pause_file(&mut self, path: Option<PathBuf>) {
+        self.pause_file = path;
+    }
+
+    /**
+     * Record `data` written to the terminal.
+     */
+    pub fn output(&mut self, data: &[u8]) {
+        let paused = self.pause_file.as_ref().is_some_and(|p| p.exists());
+        if paused != self.paused {
+            self.paused = paused;
+            self.partial.clear();
+            self.event("m", if paused { "tracking paused" } else { "tracking resumed" });
+        }
+        if paused {
+            return;
+        }
+
+        self.partial.extend_from_slice(data);
+        let valid = match std::str::from_utf8(&self.partial) {
+            Ok(_) => self.partial.len(),
+            /* incomplete sequence at the end: wait for the rest */
+            Err(err) if err.error_len().is_none() => err.valid_up_to(),
+            Err(_) => self.partial.len(),
+        };
+        if valid == 0 {
+            return;
+        }
+
+        let data: Vec<u8> = self.partial.drain(..valid).collect();
+        self.event("o", &String::from_utf8_lossy(&data));
+    }
+
+    /**
+     * Record a resize of the terminal.
+     */
+    pub fn resize(&mut self, width: u16, height: u16) {
+        self.event("r", &format!("{}x{}", width, height));
+    }
+
+    fn event(&mut self, kind: &str, data: &str) {
+        let elapsed = self.start.elapsed().as_micros() as f64 / 1_000_000.0;
+        let event = serde_json::json!([elapsed, kind, data]);
+        if let Err(err) = writeln!(self.file, "{}", event) {
+            crate::log::log_error(&format!(
+                "failed to write recording {}: {}",
+                self.path.display(),
+                err
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn asciicast_events() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session.cast");
+
+        let mut recorder = Recorder::create(&path, Some((120, 40))).unwrap();
+        recorder.output(b"$ echo \xc3");
+        recorder.output(b"\xa8\r\n");
+        recorder.resize(100, 30);
+        drop(recorder);
+
+        let contents = fs::read_to_string(&path).unwrap();
+        let lines: Vec<serde_json::Value> = contents
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines[0]["version"], 2);
+        assert_eq!(lines[0]["width"], 120);
+        assert_eq!(lines[0]["height"], 40);
+        assert_eq!(lines[1][1], "o");
+        assert_eq!(lines[1][2], "$ echo ");
+        assert_eq!(lines[2][2], "è\r\n");
+        assert_eq!(lines[3][1], "r");
+        assert_eq!(lines[3][2], "100x30");
+    }
+
+    #[test]
+    fn pause_stops_recording() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session.cast");
+        let pause_file = dir.path().join("paused");
+
+        let mut recorder = Recorder::create(&path, None).unwrap();
+        recorder.set_pause_file(Some(pause_file.clone()));
+        recorder.output(b"visible");
+        fs::write(&pause_file, "0").unwrap();
+        recorder.output(b"hidden");
+        fs::remove_file(&pause_file).unwrap();
+        recorder.output(b"visible again");
+        drop(recorder);
+
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(!contents.contains("hidden"));
+        let events: Vec<serde_json::Value> = contents
+            .lines()
+            .skip(1)
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        let kinds: Vec<&str> = events.iter().map(|e| e[1].as_str().unwrap()).collect();
+        assert_eq!(kinds, vec!["o", "m", "m", "o"]);
+    }
+}
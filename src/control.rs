@@ -0,0 +1,254 @@
+use crate::console::session_dir;
+use crate::log;
+use crate::utils::path_expand;
+
+use std::fmt;
+use std::fs;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+const CTL_TIMEOUT: u64 = 5; /* seconds */
+const TRACKER_DAEMON_SOCKET: &str = "~/.tracker/daemon.sock";
+
+#[derive(Debug, Clone)]
+pub struct ControlError(String);
+
+impl fmt::Display for ControlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for ControlError {}
+
+/**
+ * Command sent by `trk` in the tracked shell to the running tracker, or by
+ * the shell hooks to `tracker daemon` (attach).
+ */
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "cmd", content = "arg", rename_all = "lowercase")]
+pub enum Request {
+    Pause,
+    Resume,
+    Tag(String),
+    Note(String),
+    Status,
+    Attach(u32), /* pid of the shell */
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Response {
+    pub ok: bool,
+    pub message: String, /* shown to the operator */
+}
+
+impl Response {
+    pub fn ok(message: &str) -> Response {
+        Response {
+            ok: true,
+            message: message.to_owned(),
+        }
+    }
+
+    pub fn error(message: &str) -> Response {
+        Response {
+            ok: false,
+            message: message.to_owned(),
+        }
+    }
+}
+
+/**
+ * Control socket of tracker `id`, in its session dir.
+ */
+pub fn socket_path(id: u32) -> Result<PathBuf, ControlError> {
+    session_dir(id)
+        .map(|dir| dir.join("ctl.sock"))
+        .map_err(|err| ControlError(format!("control socket error: {}", err)))
+}
+
+/**
+ * Socket where `tracker daemon` waits for shells to attach.
+ */
+pub fn daemon_socket_path() -> Result<PathBuf, ControlError> {
+    path_expand(TRACKER_DAEMON_SOCKET)
+        .map_err(|err| ControlError(format!("daemon socket error: {}", err)))
+}
+
+/**
+ * Control channel of a running tracker: requests are read from a Unix
+ * socket, one JSON object per line, and answered by `handler`.
+ * The socket is removed when the server is dropped.
+ */
+pub struct ControlServer {
+    path: PathBuf,
+}
+
+impl ControlServer {
+    pub fn start<P, F>(path: P, mut handler: F) -> Result<ControlServer, ControlError>
+    where
+        P: AsRef<Path>,
+        F: FnMut(Request) -> Response + Send + 'static,
+    {
+        let path = path.as_ref().to_path_buf();
+        if path.exists() {
+            /* left by a tracker that didn't exit cleanly */
+            if UnixStream::connect(&path).is_ok() {
+                return Err(ControlError(format!(
+                    "control socket {} is in use by another tracker",
+                    path.display()
+                )));
+            }
+            let _ = fs::remove_file(&path);
+        }
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|err| {
+                ControlError(format!(
+                    "failed to create control socket dir {}: {}",
+                    dir.display(),
+                    err
+                ))
+            })?;
+        }
+
+        let listener = UnixListener::bind(&path).map_err(|err| {
+            ControlError(format!(
+                "failed to bind control socket {}: {}",
+                path.display(),
+                err
+            ))
+        })?;
+        /* only the operator may drive tracker */
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).map_err(|err| {
+            ControlError(format!(
+                "failed to protect control socket {}: {}",
+                path.display(),
+                err
+            ))
+        })?;
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        if let Err(err) = serve(stream, &mut handler) {
+                            log::log_error(&format!("control request failed: {}", err));
+                        }
+                    }
+                    Err(err) => {
+                        log::log_error(&format!("control socket error: {}", err));
+                    }
+                }
+            }
+        });
+
+        Ok(ControlServer { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for ControlServer {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn serve<F>(stream: UnixStream, handler: &mut F) -> std::io::Result<()>
+where
+    F: FnMut(Request) -> Response,
+{
+    stream.set_read_timeout(Some(Duration::from_secs(CTL_TIMEOUT)))?;
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+
+    let response = match serde_json::from_str::<Request>(&line) {
+        Ok(request) => handler(request),
+        Err(err) => Response::error(&format!("bad request: {}", err)),
+    };
+
+    let mut stream = stream;
+    writeln!(stream, "{}", serde_json::to_string(&response).unwrap())
+}
+
+/**
+ * Send `request` to the tracker listening on `path` and wait its response.
+ */
+pub fn send<P: AsRef<Path>>(path: P, request: &Request) -> Result<Response, ControlError> {
+    let path = path.as_ref();
+    let mut stream = UnixStream::connect(path).map_err(|err| {
+        ControlError(format!(
+            "tracker not reachable on {}: {}",
+            path.display(),
+            err
+        ))
+    })?;
+    stream
+        .set_read_timeout(Some(Duration::from_secs(CTL_TIMEOUT)))
+        .map_err(|err| ControlError(format!("control socket error: {}", err)))?;
+
+    writeln!(stream, "{}", serde_json::to_string(request).unwrap())
+        .map_err(|err| ControlError(format!("failed to send request: {}", err)))?;
+
+    let mut line = String::new();
+    BufReader::new(&stream)
+        .read_line(&mut line)
+        .map_err(|err| ControlError(format!("failed to read response: {}", err)))?;
+    serde_json::from_str(&line)
+        .map_err(|err| ControlError(format!("bad response: {}", err)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn request_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ctl.1.sock");
+
+        let mut notes = Vec::new();
+        let server = ControlServer::start(&path, move |request| match request {
+            Request::Note(text) => {
+                notes.push(text);
+                Response::ok(&format!("{} notes", notes.len()))
+            }
+            Request::Status => Response::ok("tracking"),
+            _ => Response::error("unsupported"),
+        })
+        .unwrap();
+
+        let note = Request::Note("found \"creds\"\nin /etc".to_owned());
+        assert_eq!(send(&path, &note).unwrap(), Response::ok("1 notes"));
+        assert_eq!(send(&path, &note).unwrap(), Response::ok("2 notes"));
+        assert_eq!(send(&path, &Request::Status).unwrap(), Response::ok("tracking"));
+        assert!(!send(&path, &Request::Pause).unwrap().ok);
+
+        /* a second tracker can't take over the socket */
+        assert!(ControlServer::start(&path, |_| Response::ok("")).is_err());
+
+        drop(server);
+        assert!(!path.exists());
+        assert!(send(&path, &Request::Status).is_err());
+    }
+
+    #[test]
+    fn request_format() {
+        assert_eq!(
+            serde_json::to_string(&Request::Tag("web".to_owned())).unwrap(),
+            r#"{"cmd":"tag","arg":"web"}"#
+        );
+        assert_eq!(serde_json::to_string(&Request::Pause).unwrap(), r#"{"cmd":"pause"}"#);
+        assert_eq!(
+            serde_json::to_string(&Request::Attach(4242)).unwrap(),
+            r#"{"cmd":"attach","arg":4242}"#
+        );
+    }
+}
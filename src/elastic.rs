@@ -1,11 +1,155 @@
+use std::convert::TryFrom;
 use std::fmt;
-use std::net::IpAddr;
+use std::fs;
+use std::net::Ipv6Addr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time;
 
+use elasticsearch::auth::Credentials;
+use elasticsearch::cert::{Certificate, CertificateValidation};
+use elasticsearch::http::headers::HeaderMap;
 use elasticsearch::http::request::JsonBody;
-use elasticsearch::http::transport::Transport;
+use elasticsearch::http::response::Response;
+use elasticsearch::http::transport::{
+    CloudConnectionPool, Connection, ConnectionPool, TransportBuilder,
+};
+use elasticsearch::http::{Method, StatusCode, Url};
 use elasticsearch::{BulkParts, Elasticsearch};
+
+use chrono::format::{Item, StrftimeItems};
+use sha2::{Digest, Sha256};
+use chrono::{TimeZone, Utc};
 use tokio;
 
+use crate::log;
+use crate::sink;
+use crate::utils::*;
+
+const RETRY_MAX: u64 = 3;
+const REQUEST_TIMEOUT: u64 = 30; /* seconds */
+
+/* bump every time the mapping below changes */
+const TEMPLATE_VERSION: u64 = 10;
+
+/* fields identifying a record, hashed to get its document id */
+const ID_FIELDS: [&str; 8] = [
+    "event.dataset",
+    "event.action",
+    "tracker.session.id",
+    "process.parent.pid",
+    "event.sequence",
+    "@timestamp",
+    "process.command_line",
+    "url.full",
+];
+
+/* browser history is read again by every session: leave the session out */
+const BROWSER_ID_FIELDS: [&str; 6] = [
+    "event.dataset",
+    "user.name",
+    "host.hostname",
+    "user_agent.name",
+    "@timestamp",
+    "url.full",
+];
+
+lazy_static! {
+    static ref MAPPING: serde_json::Value = serde_json::json!({
+        "_meta": { "tracker_template_version": TEMPLATE_VERSION },
+        "properties": {
+            "@timestamp": { "type": "date", "format": "epoch_second" },
+            "host": {
+                "properties": {
+                    "ip": { "type": "ip" },
+                    "name": { "type": "keyword" }
+                }
+            },
+            "message": { "type": "text" },
+            "process": {
+                "properties": {
+                    "command_line": {
+                        "type": "text",
+                        "fields": { "keyword": { "type": "keyword", "ignore_above": 8191 } }
+                    },
+                    "output": {
+                        "type": "text",
+                        "fields": { "keyword": { "type": "keyword", "ignore_above": 8191 } }
+                    },
+                    "output_raw": { "type": "text", "index": false },
+                    "output_bytes": { "type": "long" },
+                    "output_truncated": { "type": "boolean" },
+                    "output_binary": { "type": "boolean" },
+                    "output_sha256": { "type": "keyword" },
+                    "exit_code": { "type": "long" },
+                    "user": { "type": "keyword" },
+                    "working_directory": {
+                        "type": "keyword",
+                        "fields": { "text": { "type": "text" } }
+                    },
+                    "start": { "type": "date", "format": "epoch_millis" },
+                    "end": { "type": "date", "format": "epoch_millis" },
+                    "duration_ms": { "type": "long" },
+                    "pid": { "type": "long" },
+                    "shlvl": { "type": "long" },
+                    "nesting_depth": { "type": "long" },
+                    "tty": {
+                        "properties": {
+                            "name": { "type": "keyword" }
+                        }
+                    }
+                }
+            },
+            "tracker": {
+                "properties": {
+                    "redactions": { "type": "long" },
+                    "tag": { "type": "keyword" },
+                    "multiplexer": {
+                        "properties": {
+                            "name": { "type": "keyword" },
+                            "session": { "type": "keyword" },
+                            "window": { "type": "keyword" },
+                            "pane": { "type": "keyword" }
+                        }
+                    },
+                    "recording": {
+                        "properties": {
+                            "path": { "type": "keyword" },
+                            "offset": { "type": "float" }
+                        }
+                    }
+                }
+            },
+            "url": {
+                "properties": {
+                    "full": {
+                        "type": "text",
+                        "fields": { "keyword": { "type": "keyword", "ignore_above": 8191 } }
+                    },
+                    "visit_count": { "type": "long" }
+                }
+            },
+            "user": {
+                "properties": {
+                    "name": { "type": "keyword" },
+                    "effective": {
+                        "properties": {
+                            "name": { "type": "keyword" }
+                        }
+                    }
+                }
+            },
+            "user_agent": {
+                "properties": {
+                    "name": { "type": "keyword" }
+                }
+            }
+        }
+    });
+}
+
 #[derive(Clone, Debug)]
 pub struct ESError(String);
 
@@ -17,65 +161,1253 @@ impl fmt::Display for ESError {
 
 impl std::error::Error for ESError {}
 
-#[derive(Debug)]
+/**
+ * Failure of a whole bulk request.
+ */
+#[derive(Clone, Debug)]
+pub enum BulkError {
+    Retryable(ESError), /* cluster unreachable or busy: may be accepted later */
+    Permanent(ESError), /* refused or not understood: will fail again */
+}
+
+impl fmt::Display for BulkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BulkError::Retryable(err) => write!(f, "{}", err),
+            BulkError::Permanent(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for BulkError {}
+
+impl From<ESError> for BulkError {
+    fn from(err: ESError) -> BulkError {
+        BulkError::Retryable(err)
+    }
+}
+
+/**
+ * Outcome of a single document of a bulk request.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub enum BulkOutcome {
+    Indexed,
+    Rejected { status: u16, reason: String },
+}
+
+impl BulkOutcome {
+    /**
+     * Rejections due to a busy or unavailable cluster are worth a retry,
+     * the others (e.g. mapping conflicts) will fail again.
+     */
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            BulkOutcome::Indexed => false,
+            BulkOutcome::Rejected { status, .. } => *status == 429 || *status >= 500,
+        }
+    }
+
+    fn from_item(item: &serde_json::Value) -> BulkOutcome {
+        let result = match item.as_object().and_then(|o| o.values().next()) {
+            Some(r) => r,
+            None => {
+                return BulkOutcome::Rejected {
+                    status: 0,
+                    reason: format!("bad bulk item: {}", item),
+                }
+            }
+        };
+
+        let status = result["status"].as_u64().unwrap_or(0) as u16;
+        if result.get("error").is_none() && (200..300).contains(&status) {
+            return BulkOutcome::Indexed;
+        }
+
+        /* data streams can't overwrite: the document was already sent */
+        if status == 409 && item.get("create").is_some() {
+            return BulkOutcome::Indexed;
+        }
+
+        let error = &result["error"];
+        let reason = match (error["type"].as_str(), error["reason"].as_str()) {
+            (Some(t), Some(r)) => format!("{}: {}", t, r),
+            _ => error.to_string(),
+        };
+        BulkOutcome::Rejected { status, reason }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scheme {
+    Http,
+    Https,
+}
+
+impl fmt::Display for Scheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Scheme::Http => write!(f, "http"),
+            Scheme::Https => write!(f, "https"),
+        }
+    }
+}
+
+/**
+ * Credentials used to authenticate against the cluster.
+ */
+#[derive(Clone)]
+pub enum Auth {
+    Basic(String, String),  /* username, password */
+    ApiKey(String, String), /* id, api key */
+}
+
+impl fmt::Debug for Auth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Auth::Basic(username, _) => write!(f, "Basic({}, ***)", username),
+            Auth::ApiKey(id, _) => write!(f, "ApiKey({}, ***)", id),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct ESConfig {
-    host: IpAddr,
+    scheme: Scheme,               /* http or https */
+    host: String,                 /* IP address or hostname */
     port: u64,
-    index: String,
+    index: String,                /* index name, data stream name or date pattern (e.g. tracker-%Y.%m) */
+    data_stream: bool,            /* true to write to the data stream `index` */
+    ilm_policy: Option<String>,   /* ILM policy applied to the indices */
+    auth: Option<Auth>,           /* None if the cluster is not secured */
+    ca_cert: Option<PathBuf>,     /* PEM bundle of the CA signing the cluster certificate */
+    verify_certs: bool,           /* false to skip the cluster certificate validation */
+    nodes: Vec<String>,           /* node urls, replacing scheme/host/port if not empty */
+    cloud_id: Option<String>,     /* Elastic Cloud ID, replacing the nodes if present */
+    dead_letter: Option<PathBuf>, /* file of the rejected records, DEAD_LETTER if None */
 }
 
 impl ESConfig {
-    pub fn new(host: IpAddr, port: u64, index: &str) -> ESConfig {
+    pub fn new(host: &str, port: u64, index: &str) -> ESConfig {
         ESConfig {
-            host,
+            scheme: Scheme::Http,
+            host: host.to_owned(),
             port,
             index: index.to_owned(),
+            data_stream: false,
+            ilm_policy: None,
+            auth: None,
+            ca_cert: None,
+            verify_certs: true,
+            nodes: Vec::new(),
+            cloud_id: None,
+            dead_letter: None,
         }
     }
+
+    pub fn with_dead_letter(mut self, dead_letter: Option<PathBuf>) -> ESConfig {
+        self.dead_letter = dead_letter;
+        self
+    }
+
+    pub fn with_data_stream(mut self, data_stream: bool) -> ESConfig {
+        self.data_stream = data_stream;
+        self
+    }
+
+    pub fn with_ilm_policy(mut self, ilm_policy: Option<String>) -> ESConfig {
+        self.ilm_policy = ilm_policy;
+        self
+    }
+
+    pub fn with_nodes(mut self, nodes: Vec<String>) -> ESConfig {
+        self.nodes = nodes;
+        self
+    }
+
+    pub fn with_cloud_id(mut self, cloud_id: Option<String>) -> ESConfig {
+        self.cloud_id = cloud_id;
+        self
+    }
+
+    pub fn with_scheme(mut self, scheme: Scheme) -> ESConfig {
+        self.scheme = scheme;
+        self
+    }
+
+    pub fn with_auth(mut self, auth: Option<Auth>) -> ESConfig {
+        self.auth = auth;
+        self
+    }
+
+    pub fn with_ca_cert<P: AsRef<Path>>(mut self, ca_cert: Option<P>) -> ESConfig {
+        self.ca_cert = ca_cert.map(|p| p.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn with_verify_certs(mut self, verify_certs: bool) -> ESConfig {
+        self.verify_certs = verify_certs;
+        self
+    }
+
+    /**
+     * Index (or data stream) where a record with timestamp `timestamp`
+     * is written. A timestamp out of range goes to the index of the epoch.
+     */
+    pub fn index_for(&self, timestamp: u64) -> String {
+        if !is_index_pattern(&self.index) {
+            return self.index.clone();
+        }
+        let date = i64::try_from(timestamp)
+            .ok()
+            .and_then(|timestamp| Utc.timestamp_opt(timestamp, 0).single())
+            .unwrap_or_else(|| Utc.timestamp_opt(0, 0).unwrap());
+        date.format(&self.index).to_string().to_lowercase()
+    }
+
+    /**
+     * Wildcard expression matching every index written by tracker.
+     */
+    fn index_glob(&self) -> String {
+        if !is_index_pattern(&self.index) {
+            return self.index.clone();
+        }
+        let re = regex::Regex::new(
+            r#"%[-_0^#]?[0-9]*[a-zA-Z%]([^a-zA-Z0-9%]*%[-_0^#]?[0-9]*[a-zA-Z%])*"#,
+        )
+        .unwrap();
+        re.replace_all(&self.index, "*").to_string().to_lowercase()
+    }
+
+    /**
+     * Name of the index template, taken from the part of the index name
+     * before any date.
+     */
+    fn template_name(&self) -> String {
+        let prefix = self.index.split('%').next().unwrap();
+        let name = prefix.trim_end_matches(&['-', '.', '_'][..]);
+        if name.is_empty() {
+            "tracker".to_owned()
+        } else {
+            name.to_lowercase()
+        }
+    }
+
+    fn urls(&self) -> Result<Vec<Url>, ESError> {
+        let urls = if self.nodes.is_empty() {
+            let host = match self.host.parse::<Ipv6Addr>() {
+                Ok(_) => format!("[{}]", self.host),
+                Err(_) => self.host.clone(),
+            };
+            vec![format!("{}://{}:{}", self.scheme, host, self.port)]
+        } else {
+            self.nodes.clone()
+        };
+
+        urls.iter()
+            .map(|url| {
+                Url::parse(url)
+                    .map_err(|err| ESError(format!("bad url {}: {}", url, err)))
+            })
+            .collect()
+    }
+
+    fn cert_validation(&self) -> Result<CertificateValidation, ESError> {
+        if !self.verify_certs {
+            return Ok(CertificateValidation::None);
+        }
+
+        match &self.ca_cert {
+            Some(path) => {
+                let pem = fs::read(path).map_err(|err| {
+                    ESError(format!(
+                        "failed to read CA bundle {}: {}",
+                        path.display(),
+                        err
+                    ))
+                })?;
+                let cert = Certificate::from_pem(&pem).map_err(|err| {
+                    ESError(format!(
+                        "bad CA bundle {}: {}",
+                        path.display(),
+                        err
+                    ))
+                })?;
+                Ok(CertificateValidation::Full(cert))
+            }
+            None => Ok(CertificateValidation::Default),
+        }
+    }
+}
+
+/**
+ * Connection pool over a static list of nodes.
+ * Requests stick to the current node until it fails, then `failover`
+ * moves them to the next one. Clones share the current node.
+ */
+#[derive(Clone, Debug)]
+pub struct FailoverConnectionPool {
+    urls: Vec<Url>,               /* node urls */
+    connections: Vec<Connection>, /* one connection per node */
+    current: Arc<AtomicUsize>,    /* index of the node in use */
+}
+
+impl FailoverConnectionPool {
+    pub fn new(urls: Vec<Url>) -> FailoverConnectionPool {
+        FailoverConnectionPool {
+            connections: urls.iter().cloned().map(Connection::new).collect(),
+            urls,
+            current: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub fn current(&self) -> &Url {
+        &self.urls[self.current.load(Ordering::SeqCst) % self.urls.len()]
+    }
+
+    pub fn len(&self) -> usize {
+        self.connections.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.connections.is_empty()
+    }
+
+    pub fn failover(&self) {
+        let n = self.connections.len();
+        let _ = self
+            .current
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |c| Some((c + 1) % n));
+    }
+}
+
+impl ConnectionPool for FailoverConnectionPool {
+    fn next(&self) -> &Connection {
+        &self.connections[self.current.load(Ordering::SeqCst) % self.connections.len()]
+    }
 }
 
 #[derive(Debug)]
 pub struct ESClient {
-    config: ESConfig,      /* ES configuration */
-    client: Elasticsearch, /* the client themself */
+    config: ESConfig,                     /* ES configuration */
+    client: Elasticsearch,                /* the client themself */
+    pool: Option<FailoverConnectionPool>, /* nodes in use, None with a Cloud ID */
+    ready: AtomicBool,                    /* true once the template is installed */
 }
 
 impl ESClient {
-    pub fn new(config: ESConfig) -> ESClient {
-        let url = format!("http://{}:{}", &config.host.to_string(), &config.port);
-        let transport = Transport::single_node(&url).unwrap();
+    pub fn new(config: ESConfig) -> Result<ESClient, ESError> {
+        let mut pool = None;
+        let mut builder = match &config.cloud_id {
+            Some(cloud_id) => {
+                let cloud = CloudConnectionPool::new(cloud_id)
+                    .map_err(|err| ESError(format!("bad cloud id: {}", err)))?;
+                TransportBuilder::new(cloud)
+            }
+            None => {
+                let urls = config.urls()?;
+                if urls.is_empty() {
+                    return Err(ESError("no node configured".to_owned()));
+                }
+                let failover = FailoverConnectionPool::new(urls);
+                pool = Some(failover.clone());
+                TransportBuilder::new(failover)
+            }
+        };
+
+        builder = builder
+            .timeout(time::Duration::from_secs(REQUEST_TIMEOUT))
+            .cert_validation(config.cert_validation()?);
+        builder = match &config.auth {
+            Some(Auth::Basic(username, password)) => {
+                builder.auth(Credentials::Basic(username.clone(), password.clone()))
+            }
+            Some(Auth::ApiKey(id, key)) => builder.auth(Credentials::ApiKey(id.clone(), key.clone())),
+            None => builder,
+        };
+
+        let transport = builder
+            .build()
+            .map_err(|err| ESError(format!("failed to build transport: {}", err)))?;
         let client = Elasticsearch::new(transport);
-        ESClient { config, client }
+        Ok(ESClient {
+            config,
+            client,
+            pool,
+            ready: AtomicBool::new(false),
+        })
     }
 
-    pub fn from(host: IpAddr, port: u64, index: &str) -> ESClient {
+    pub fn from(host: &str, port: u64, index: &str) -> Result<ESClient, ESError> {
         let config = ESConfig::new(host, port, index);
         ESClient::new(config)
     }
 
-    #[tokio::main]
-    pub async fn bulk_import(&self, records: Vec<serde_json::Value>) -> Result<(), ESError> {
-        let mut body: Vec<JsonBody<_>> = vec![];
-        for record in records {
-            body.push(serde_json::json!({"index": {}}).into());
-            body.push(record.into());
-        }
-
-        return match self
-            .client
-            .bulk(BulkParts::Index(&self.config.index))
-            .body(body)
-            .send()
+    /**
+     * Number of nodes a request can be tried on.
+     */
+    fn nodes(&self) -> usize {
+        match &self.pool {
+            Some(pool) => pool.len(),
+            None => 1,
+        }
+    }
+
+    /**
+     * Move the next requests to another node after `reason`.
+     */
+    fn failover(&self, reason: &str) {
+        if let Some(pool) = &self.pool {
+            log::log_error(&format!(
+                "node {} failed, switching to the next one: {}",
+                pool.current(),
+                reason
+            ));
+            pool.failover();
+        }
+    }
+
+    /**
+     * Send a request with a JSON body, moving to the next node on failure.
+     */
+    async fn send(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&serde_json::Value>,
+    ) -> Result<Response, ESError> {
+        let mut attempts = self.nodes();
+        loop {
+            let json_body = body.map(|b| JsonBody::new(b.clone()));
+            match self
+                .client
+                .send(method, path, HeaderMap::new(), None::<&()>, json_body, None)
+                .await
+            {
+                Ok(response) => return Ok(response),
+                Err(err) => {
+                    attempts -= 1;
+                    if attempts == 0 {
+                        return Err(ESError(format!(
+                            "request {} failed: {}",
+                            path,
+                            err
+                        )));
+                    }
+                    self.failover(&err.to_string());
+                }
+            }
+        }
+    }
+
+    async fn json(&self, response: Response) -> Result<serde_json::Value, ESError> {
+        let status = response.status_code();
+        let body = response
+            .json::<serde_json::Value>()
             .await
-        {
-            Ok(response) => {
-                if response.status_code().is_success() {
-                    Ok(())
-                } else {
-                    Err(ESError(format!("records not updated: status code: {}", response.status_code())))
+            .map_err(|err| ESError(format!("bad response: {}", err)))?;
+
+        if !status.is_success() {
+            return Err(ESError(format!("status code {}: {}", status, body["error"])));
+        }
+        Ok(body)
+    }
+
+    /**
+     * Install the index template, unless an equal or newer version is
+     * already there, and add the fields they miss to existing indices.
+     * Fields an index maps differently can't be changed: they make the
+     * setup fail. Credentials that may only write documents skip what they
+     * are not allowed to do, with a warning.
+     */
+    #[tokio::main]
+    async fn install_template(&self) -> Result<Vec<String>, ESError> {
+        let name = self.config.template_name();
+        let index = self.config.index_glob();
+        let path = format!("/_index_template/{}", name);
+        let mut warnings = Vec::new();
+
+        let response = self.send(Method::Get, &path, None).await?;
+        let installed = if is_forbidden(&response) {
+            warnings.push(format!(
+                "index template {} not installed: status code {}, the credentials can't manage templates",
+                name,
+                response.status_code()
+            ));
+            TEMPLATE_VERSION
+        } else if response.status_code() == StatusCode::NOT_FOUND {
+            0
+        } else {
+            let body = self.json(response).await?;
+            body["index_templates"][0]["index_template"]["version"]
+                .as_u64()
+                .unwrap_or(0)
+        };
+
+        if installed < TEMPLATE_VERSION {
+            let mut template = serde_json::json!({
+                "index_patterns": [index],
+                "version": TEMPLATE_VERSION,
+                "priority": 200,
+                "template": { "mappings": *MAPPING },
+            });
+            if self.config.data_stream {
+                template["data_stream"] = serde_json::json!({});
+            }
+            if let Some(policy) = &self.config.ilm_policy {
+                template["template"]["settings"] = serde_json::json!({
+                    "index.lifecycle.name": policy,
+                });
+            }
+            let response = self.send(Method::Put, &path, Some(&template)).await?;
+            if is_forbidden(&response) {
+                warnings.push(format!(
+                    "index template {} not installed: status code {}, the credentials can't manage templates",
+                    name,
+                    response.status_code()
+                ));
+            } else {
+                self.json(response)
+                    .await
+                    .map_err(|err| ESError(format!("failed to install template: {}", err)))?;
+                log::log_info(&format!(
+                    "installed index template {} version {}",
+                    name, TEMPLATE_VERSION
+                ));
+            }
+        }
+
+        let mut conflicts = Vec::new();
+        let path = format!("/{}/_mapping", index);
+        let response = self.send(Method::Get, &path, None).await?;
+        let body = if is_forbidden(&response) {
+            warnings.push(format!(
+                "mappings of {} not checked: status code {}, the credentials can't read them",
+                index,
+                response.status_code()
+            ));
+            serde_json::Value::Null
+        } else if response.status_code() == StatusCode::NOT_FOUND {
+            serde_json::Value::Null
+        } else {
+            self.json(response).await?
+        };
+        for (name, mapping) in body.as_object().into_iter().flatten() {
+            let mut found = Vec::new();
+            let missing = missing_fields(&mapping["mappings"], &MAPPING, "", &mut found);
+            conflicts.extend(found.into_iter().map(|conflict| format!("index {}: {}", name, conflict)));
+            if missing.is_empty() {
+                continue;
+            }
+
+            let path = format!("/{}/_mapping", name);
+            let body = serde_json::json!({ "properties": missing });
+            let response = self.send(Method::Put, &path, Some(&body)).await?;
+            if let Err(err) = self.json(response).await {
+                warnings.push(format!("index {}: failed to add missing fields: {}", name, err));
+            }
+        }
+
+        for warning in &warnings {
+            log::log_warning(warning);
+        }
+        if !conflicts.is_empty() {
+            return Err(ESError(format!("incompatible mapping: {}", conflicts.join("; "))));
+        }
+        Ok(warnings)
+    }
+
+    /**
+     * Make sure the template and the mappings are in place, returning what
+     * the credentials didn't allow to check or install.
+     * An unreachable cluster is not an error, the setup is retried by the
+     * next `import`. An existing index with an incompatible mapping is: the
+     * records are kept in the spool until it is fixed.
+     */
+    pub fn setup(&self) -> Result<Vec<String>, ESError> {
+        if self.ready.load(Ordering::SeqCst) {
+            return Ok(Vec::new());
+        }
+
+        if let Err(err) = self.ping() {
+            log::log_error(&format!("index template not installed: {}", err));
+            return Ok(Vec::new());
+        }
+
+        let warnings = self
+            .install_template()
+            .inspect_err(|err| log::log_error(&err.to_string()))?;
+        self.ready.store(true, Ordering::SeqCst);
+        Ok(warnings)
+    }
+
+    #[tokio::main]
+    async fn ping(&self) -> Result<(), ESError> {
+        self.send(Method::Head, "/", None).await?;
+        Ok(())
+    }
+
+    /**
+     * Bulk action line for `record`.
+     * Data streams only accept the `create` operation.
+     */
+    fn action(&self, record: &serde_json::Value) -> serde_json::Value {
+        let timestamp = record["@timestamp"].as_u64().unwrap_or_else(timestamp_now);
+        let index = self.config.index_for(timestamp);
+        let id = document_id(record);
+        if self.config.data_stream {
+            serde_json::json!({"create": {"_index": index, "_id": id}})
+        } else {
+            serde_json::json!({"index": {"_index": index, "_id": id}})
+        }
+    }
+
+    /**
+     * Send `records` with a bulk request and return the outcome of each of
+     * them, in the same order.
+     * A request refused as a whole (4xx other than 401, 403, 408 and 429) or
+     * answered with a malformed response is a permanent error: sending the
+     * same records again would fail the same way.
+     */
+    #[tokio::main]
+    pub async fn bulk_import(
+        &self,
+        records: Vec<serde_json::Value>,
+    ) -> Result<Vec<BulkOutcome>, BulkError> {
+        let n = records.len();
+        let mut attempts = self.nodes();
+        let response = loop {
+            let mut body: Vec<JsonBody<_>> = vec![];
+            for record in &records {
+                body.push(self.action(record).into());
+                body.push(record.clone().into());
+            }
+
+            match self
+                .client
+                .bulk(BulkParts::None)
+                .body(body)
+                .send()
+                .await
+            {
+                Ok(response) => break response,
+                Err(err) => {
+                    attempts -= 1;
+                    if attempts == 0 {
+                        return Err(BulkError::Retryable(ESError(format!(
+                            "records not updated: error: {}",
+                            err
+                        ))));
+                    }
+                    self.failover(&err.to_string());
                 }
             }
-            Err(err) => Err(ESError(format!("records not updated: error: {}", err.to_string()))),
         };
+
+        let status = response.status_code();
+        if !status.is_success() {
+            let err = ESError(format!("records not updated: status code: {}", status));
+            /* credentials may be fixed, the cluster may catch up */
+            return match status.as_u16() {
+                401 | 403 | 408 | 429 => Err(BulkError::Retryable(err)),
+                code if (400..500).contains(&code) => Err(BulkError::Permanent(err)),
+                _ => Err(BulkError::Retryable(err)),
+            };
+        }
+
+        let response = response.json::<serde_json::Value>().await.map_err(|err| {
+            BulkError::Permanent(ESError(format!("bad bulk response: {}", err)))
+        })?;
+
+        let items = match response["items"].as_array() {
+            Some(items) if items.len() == n => items,
+            _ => {
+                return Err(BulkError::Permanent(ESError(format!(
+                    "bad bulk response: {}",
+                    response
+                ))))
+            }
+        };
+
+        Ok(items.iter().map(BulkOutcome::from_item).collect())
+    }
+
+    /**
+     * Send `records`, retrying the documents rejected for transient reasons.
+     * Documents that cannot be indexed are appended to the dead letter file.
+     */
+    pub fn import(&self, records: &[serde_json::Value]) -> Result<(), BulkError> {
+        self.setup()?;
+        if !self.ready.load(Ordering::SeqCst) {
+            return Err(BulkError::Retryable(ESError("cluster not reachable".to_owned())));
+        }
+
+        let mut pending = records.to_vec();
+
+        for attempt in 1..=RETRY_MAX {
+            let outcomes = self.bulk_import(pending.clone())?;
+
+            let mut retry = Vec::new();
+            for (record, outcome) in pending.into_iter().zip(outcomes) {
+                if outcome == BulkOutcome::Indexed {
+                    continue;
+                }
+                if outcome.is_retryable() && attempt < RETRY_MAX {
+                    retry.push(record);
+                } else {
+                    self.dead_letter(&record, &outcome);
+                }
+            }
+
+            if retry.is_empty() {
+                break;
+            }
+            thread::sleep(time::Duration::from_millis(500 * attempt));
+            pending = retry;
+        }
+
+        Ok(())
+    }
+
+    fn dead_letter(&self, record: &serde_json::Value, outcome: &BulkOutcome) {
+        if let BulkOutcome::Rejected { status, reason } = outcome {
+            let path = self.config.dead_letter.as_deref();
+            sink::dead_letter(path, std::slice::from_ref(record), *status, reason);
+        }
+    }
+}
+
+/**
+ * Deterministic id of `record`: sending the same record twice overwrites
+ * the first document instead of creating a duplicate.
+ */
+pub fn document_id(record: &serde_json::Value) -> String {
+    let fields = match record["event.dataset"].as_str() {
+        Some("tracker.browser") => &BROWSER_ID_FIELDS[..],
+        _ => &ID_FIELDS[..],
+    };
+
+    let mut hasher = Sha256::new();
+    for field in fields {
+        hasher.update(field.as_bytes());
+        hasher.update(b"=");
+        hasher.update(record[*field].to_string().as_bytes());
+        hasher.update(b"\0");
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+/**
+ * True if `index` contains date specifiers (e.g. tracker-%Y.%m.%d).
+ */
+pub fn is_index_pattern(index: &str) -> bool {
+    index.contains('%')
+}
+
+/**
+ * True if `cloud_id` is a valid Elastic Cloud ID
+ * (<name>:<base64 of host$es_uuid$kibana_uuid>).
+ */
+pub fn is_cloud_id(cloud_id: &str) -> bool {
+    CloudConnectionPool::new(cloud_id).is_ok()
+}
+
+/**
+ * Check that the date specifiers of `index` are valid.
+ */
+pub fn check_index_pattern(index: &str) -> bool {
+    !StrftimeItems::new(index).any(|item| item == Item::Error)
+}
+
+/**
+ * Whether the credentials were refused the request of `response`.
+ */
+fn is_forbidden(response: &Response) -> bool {
+    let status = response.status_code();
+    status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN
+}
+
+/**
+ * Type of the mapped `field` (e.g. date (epoch_second)), "object" for
+ * objects.
+ */
+fn field_type(field: &serde_json::Value) -> String {
+    let kind = field["type"].as_str().unwrap_or("object");
+    match field["format"].as_str() {
+        Some(format) => format!("{} ({})", kind, format),
+        None => kind.to_owned(),
+    }
+}
+
+/**
+ * Fields of the tracker mapping `expected` missing from the index mapping
+ * `mapped`, with their mapping. The fields mapped with another type or
+ * format are left out and reported in `conflicts`.
+ */
+fn missing_fields(
+    mapped: &serde_json::Value,
+    expected: &serde_json::Value,
+    prefix: &str,
+    conflicts: &mut Vec<String>,
+) -> serde_json::Map<String, serde_json::Value> {
+    let mut missing = serde_json::Map::new();
+    let fields = match expected["properties"].as_object() {
+        Some(fields) => fields,
+        None => return missing,
+    };
+
+    for (name, field) in fields {
+        let found = &mapped["properties"][name];
+        let path = format!("{}{}", prefix, name);
+        if found.is_null() {
+            missing.insert(name.clone(), field.clone());
+        } else if field_type(found) != field_type(field) {
+            conflicts.push(format!(
+                "{} is mapped as {}, expected {}",
+                path,
+                field_type(found),
+                field_type(field)
+            ));
+        } else if field.get("properties").is_some() {
+            let inner = missing_fields(found, field, &format!("{}.", path), conflicts);
+            if !inner.is_empty() {
+                missing.insert(name.clone(), serde_json::json!({ "properties": inner }));
+            }
+        }
+    }
+    missing
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+    use std::io::prelude::*;
+    use std::io::BufReader;
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Cluster {
+        documents: HashMap<String, serde_json::Value>, /* indexed documents by id */
+        attempts: HashMap<String, usize>,              /* bulk items received by id */
+        template: serde_json::Value,                   /* installed index template */
+        mappings: serde_json::Value,                   /* mappings of the existing indices */
+        updates: Vec<(String, serde_json::Value)>,     /* PUT requests, path and body */
+        write_only: bool,                              /* refuse everything but bulk requests */
+    }
+
+    type Mock = Arc<Mutex<Cluster>>;
+
+    /**
+     * Status of the `attempt`th bulk item of `record`: the one scripted in
+     * its `mock.status` list (the last one repeating), 201 by default.
+     */
+    fn mock_status(record: &serde_json::Value, attempt: usize) -> u64 {
+        match record["mock.status"].as_array() {
+            Some(statuses) => statuses[std::cmp::min(attempt, statuses.len() - 1)]
+                .as_u64()
+                .unwrap(),
+            None => 201,
+        }
+    }
+
+    /**
+     * Answer the requests of a connection like a bare Elasticsearch node,
+     * storing the bulk documents by id and the index template. Bulk
+     * requests are refused as a whole with the `mock.bulk_status` of their
+     * first record, a 200 yielding a response without items. A `write_only`
+     * cluster refuses the template and mapping requests with a 403.
+     */
+    fn mock_connection(stream: TcpStream, cluster: Mock) {
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut stream = stream;
+        loop {
+            let mut request = String::new();
+            if reader.read_line(&mut request).unwrap_or(0) == 0 {
+                return;
+            }
+
+            let mut length = 0;
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+                if header == "\r\n" {
+                    break;
+                }
+                let header = header.to_lowercase();
+                if let Some(l) = header.strip_prefix("content-length:") {
+                    length = l.trim().parse::<usize>().unwrap();
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            let body = String::from_utf8(body).unwrap();
+
+            let mut parts = request.split_whitespace();
+            let method = parts.next().unwrap().to_owned();
+            let path = parts.next().unwrap().to_owned();
+
+            let forbidden = method != "HEAD" && !path.starts_with("/_bulk");
+            let (status, response) = if forbidden && cluster.lock().unwrap().write_only {
+                ("403 Forbidden", serde_json::json!({"error": "unauthorized"}))
+            } else if path.starts_with("/_bulk") {
+                let lines: Vec<serde_json::Value> = body
+                    .lines()
+                    .map(|l| serde_json::from_str(l).unwrap())
+                    .collect();
+                let mut cluster = cluster.lock().unwrap();
+                let mut items = Vec::new();
+                for pair in lines.chunks(2) {
+                    let id = pair[0]["index"]["_id"].as_str().unwrap().to_owned();
+                    let attempt = cluster.attempts.entry(id.clone()).or_insert(0);
+                    let status = mock_status(&pair[1], *attempt);
+                    *attempt += 1;
+
+                    let item = if status == 201 {
+                        let replaced = cluster.documents.insert(id.clone(), pair[1].clone());
+                        let status = if replaced.is_some() { 200 } else { 201 };
+                        serde_json::json!({"_id": id, "status": status})
+                    } else {
+                        let error =
+                            serde_json::json!({"type": "mock_exception", "reason": "mocked"});
+                        serde_json::json!({"_id": id, "status": status, "error": error})
+                    };
+                    items.push(serde_json::json!({ "index": item }));
+                }
+
+                match lines[1]["mock.bulk_status"].as_u64() {
+                    Some(200) => ("200 OK", serde_json::json!({"took": 1})),
+                    Some(413) => {
+                        ("413 Payload Too Large", serde_json::json!({"error": "too large"}))
+                    }
+                    Some(503) => ("503 Service Unavailable", serde_json::json!({"error": "busy"})),
+                    _ => ("200 OK", serde_json::json!({"errors": false, "items": items})),
+                }
+            } else if method == "GET" {
+                let cluster = cluster.lock().unwrap();
+                if path.starts_with("/_index_template/") && !cluster.template.is_null() {
+                    let template = serde_json::json!({"index_template": cluster.template});
+                    ("200 OK", serde_json::json!({ "index_templates": [template] }))
+                } else if path.ends_with("/_mapping") && !cluster.mappings.is_null() {
+                    ("200 OK", cluster.mappings.clone())
+                } else {
+                    ("404 Not Found", serde_json::json!({"error": "not found"}))
+                }
+            } else {
+                if method == "PUT" {
+                    let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+                    let mut cluster = cluster.lock().unwrap();
+                    if path.starts_with("/_index_template/") {
+                        cluster.template = body.clone();
+                    }
+                    cluster.updates.push((path, body));
+                }
+                ("200 OK", serde_json::json!({"acknowledged": true}))
+            };
+
+            let response = if method == "HEAD" {
+                String::new()
+            } else {
+                response.to_string()
+            };
+            write!(
+                stream,
+                "HTTP/1.1 {}\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}",
+                status,
+                response.len(),
+                response
+            )
+            .unwrap();
+        }
+    }
+
+    fn mock_es() -> (u16, Mock) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let cluster: Mock = Arc::new(Mutex::new(Cluster::default()));
+
+        let server_cluster = Arc::clone(&cluster);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let cluster = Arc::clone(&server_cluster);
+                thread::spawn(move || mock_connection(stream.unwrap(), cluster));
+            }
+        });
+
+        (port, cluster)
+    }
+
+    fn history() -> Vec<serde_json::Value> {
+        vec![
+            serde_json::json!({
+                "@timestamp": 1600000000,
+                "event.dataset": "tracker.console",
+                "event.sequence": 0,
+                "process.parent.pid": 4242,
+                "process.command_line": "ls -la",
+            }),
+            serde_json::json!({
+                "@timestamp": 1600000000,
+                "event.dataset": "tracker.console",
+                "event.sequence": 1,
+                "process.parent.pid": 4242,
+                "process.command_line": "ls -la",
+            }),
+            serde_json::json!({
+                "@timestamp": 1600000005,
+                "event.dataset": "tracker.browser",
+                "url.full": "https://example.com/",
+            }),
+        ]
+    }
+
+    #[test]
+    fn document_id_is_deterministic() {
+        let records = history();
+        assert_eq!(document_id(&records[0]), document_id(&records[0].clone()));
+        assert_ne!(document_id(&records[0]), document_id(&records[1]));
+        assert_ne!(document_id(&records[0]), document_id(&records[2]));
+    }
+
+    #[test]
+    fn dated_index_names() {
+        let config = ESConfig::new("localhost", 9200, "Tracker-%Y.%m.%d");
+        /* 2020-12-31T23:59:59Z */
+        assert_eq!(config.index_for(1609459199), "tracker-2020.12.31");
+        assert_eq!(config.index_for(1609459200), "tracker-2021.01.01");
+        assert_eq!(config.index_for(1 << 60), "tracker-1970.01.01");
+        assert_eq!(config.index_for(u64::MAX), "tracker-1970.01.01");
+        assert_eq!(config.index_glob(), "tracker-*");
+        assert_eq!(config.template_name(), "tracker");
+
+        /* the ISO week of 2021-01-01 belongs to 2020 */
+        let config = ESConfig::new("localhost", 9200, "tracker-%G-w%V");
+        assert_eq!(config.index_for(1609459200), "tracker-2020-w53");
+        assert_eq!(config.index_glob(), "tracker-*-w*");
+
+        let config = ESConfig::new("localhost", 9200, "tracker");
+        assert_eq!(config.index_for(1609459200), "tracker");
+        assert_eq!(config.index_glob(), "tracker");
+    }
+
+    #[test]
+    fn index_patterns() {
+        assert!(!is_index_pattern("tracker"));
+        for pattern in &["tracker-%Y", "tracker-%Y.%m.%d", "%Y-tracker", "tracker-%G.%V"] {
+            assert!(is_index_pattern(pattern));
+            assert!(check_index_pattern(pattern), "{}", pattern);
+        }
+        for pattern in &["tracker-%", "tracker-%Y%", "tracker-%Q", "tracker-%!"] {
+            assert!(!check_index_pattern(pattern), "{}", pattern);
+        }
+    }
+
+    #[test]
+    fn resent_history_overwrites() {
+        let (port, cluster) = mock_es();
+        let client = ESClient::from("127.0.0.1", port as u64, "tracker").unwrap();
+
+        client.import(&history()).unwrap();
+        assert_eq!(cluster.lock().unwrap().documents.len(), 3);
+
+        /* restart from the start of the history, or retry after a failure */
+        client.import(&history()).unwrap();
+        client.import(&history()[1..]).unwrap();
+        assert_eq!(cluster.lock().unwrap().documents.len(), 3);
+    }
+
+    #[test]
+    fn bulk_item_outcomes() {
+        let indexed = serde_json::json!({"index": {"_id": "a", "status": 201}});
+        assert_eq!(BulkOutcome::from_item(&indexed), BulkOutcome::Indexed);
+
+        /* already sent to the data stream */
+        let created = serde_json::json!({"create": {"_id": "a", "status": 409}});
+        assert_eq!(BulkOutcome::from_item(&created), BulkOutcome::Indexed);
+
+        let error = serde_json::json!({"type": "mapper_parsing_exception", "reason": "bad"});
+        let rejected = serde_json::json!({"index": {"status": 400, "error": error}});
+        let outcome = BulkOutcome::from_item(&rejected);
+        assert_eq!(
+            outcome,
+            BulkOutcome::Rejected {
+                status: 400,
+                reason: "mapper_parsing_exception: bad".to_owned()
+            }
+        );
+        assert!(!outcome.is_retryable());
+
+        let busy = serde_json::json!({"index": {"status": 429, "error": {"type": "rejected"}}});
+        assert!(BulkOutcome::from_item(&busy).is_retryable());
+        let down = serde_json::json!({"index": {"status": 503, "error": {}}});
+        assert!(BulkOutcome::from_item(&down).is_retryable());
+    }
+
+    #[test]
+    fn rejected_records_retried_then_dead_lettered() {
+        let dir = tempfile::tempdir().unwrap();
+        let dead_letter = dir.path().join("deadletter.ndjson");
+        let (port, cluster) = mock_es();
+        let config = ESConfig::new("127.0.0.1", port as u64, "tracker")
+            .with_dead_letter(Some(dead_letter.clone()));
+        let client = ESClient::new(config).unwrap();
+
+        let mut records = history();
+        records[0]["mock.status"] = serde_json::json!([400]);
+        records[1]["mock.status"] = serde_json::json!([429, 429, 201]);
+        records[2]["mock.status"] = serde_json::json!([429]);
+        client.import(&records).unwrap();
+
+        let cluster = cluster.lock().unwrap();
+        let attempts: Vec<usize> = records
+            .iter()
+            .map(|record| cluster.attempts[&document_id(record)])
+            .collect();
+        assert_eq!(attempts, vec![1, RETRY_MAX as usize, RETRY_MAX as usize]);
+        assert_eq!(cluster.documents.len(), 1);
+        assert!(cluster.documents.contains_key(&document_id(&records[1])));
+
+        let entries: Vec<serde_json::Value> = fs::read_to_string(&dead_letter)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0]["status"], 400);
+        assert_eq!(entries[0]["reason"], "mock_exception: mocked");
+        assert_eq!(entries[0]["record"], records[0]);
+        assert_eq!(entries[1]["status"], 429);
+        assert_eq!(entries[1]["record"], records[2]);
+    }
+
+    #[test]
+    fn refused_bulk_errors() {
+        let (port, _) = mock_es();
+        let client = ESClient::from("127.0.0.1", port as u64, "tracker").unwrap();
+
+        let mut records = history();
+        records[0]["mock.bulk_status"] = serde_json::json!(413);
+        assert!(matches!(client.import(&records), Err(BulkError::Permanent(_))));
+
+        /* malformed response */
+        records[0]["mock.bulk_status"] = serde_json::json!(200);
+        assert!(matches!(client.import(&records), Err(BulkError::Permanent(_))));
+
+        records[0]["mock.bulk_status"] = serde_json::json!(503);
+        assert!(matches!(client.import(&records), Err(BulkError::Retryable(_))));
+    }
+
+    #[test]
+    fn missing_fields_only() {
+        let mapped = serde_json::json!({
+            "properties": {
+                "@timestamp": { "type": "date" },
+                "message": { "type": "text" },
+                "process": {
+                    "properties": {
+                        "command_line": { "type": "keyword" },
+                        "pid": { "type": "long" }
+                    }
+                },
+                "user": { "type": "keyword" }
+            }
+        });
+
+        let mut conflicts = Vec::new();
+        let missing = missing_fields(&mapped, &MAPPING, "", &mut conflicts);
+        assert_eq!(
+            conflicts,
+            vec![
+                "@timestamp is mapped as date, expected date (epoch_second)",
+                "process.command_line is mapped as keyword, expected text",
+                "user is mapped as keyword, expected object",
+            ]
+        );
+        assert!(!missing.contains_key("@timestamp"));
+        assert!(!missing.contains_key("message"));
+        assert!(!missing.contains_key("user"));
+        assert_eq!(missing["host"], MAPPING["properties"]["host"]);
+        let process = missing["process"]["properties"].as_object().unwrap();
+        assert!(!process.contains_key("command_line"));
+        assert!(!process.contains_key("pid"));
+        assert_eq!(process["output"], MAPPING["properties"]["process"]["properties"]["output"]);
+
+        let mut conflicts = Vec::new();
+        assert!(missing_fields(&MAPPING, &MAPPING, "", &mut conflicts).is_empty());
+        assert!(conflicts.is_empty());
+    }
+
+    #[test]
+    fn setup_with_existing_indices() {
+        let (port, cluster) = mock_es();
+        cluster.lock().unwrap().mappings = serde_json::json!({
+            "tracker-old": {
+                "mappings": {
+                    "properties": {
+                        "@timestamp": { "type": "date", "format": "strict_date_optional_time" },
+                        "process": {
+                            "properties": { "command_line": { "type": "text" } }
+                        }
+                    }
+                }
+            },
+            "tracker-new": { "mappings": MAPPING.clone() },
+        });
+        let client = ESClient::from("127.0.0.1", port as u64, "tracker-%Y").unwrap();
+
+        /* the conflict is fatal, the missing fields are added anyway */
+        let err = client.setup().unwrap_err();
+        assert_eq!(
+            err.0,
+            "incompatible mapping: index tracker-old: @timestamp is mapped as date \
+             (strict_date_optional_time), expected date (epoch_second)"
+        );
+
+        let updates = cluster.lock().unwrap().updates.clone();
+        assert_eq!(updates.len(), 2);
+        assert!(updates[0].0.starts_with("/_index_template/"));
+        assert_eq!(updates[0].1["version"], TEMPLATE_VERSION);
+        assert_eq!(updates[0].1["template"]["mappings"], *MAPPING);
+
+        /* only the missing fields of the index */
+        assert_eq!(updates[1].0, "/tracker-old/_mapping");
+        let properties = updates[1].1["properties"].as_object().unwrap();
+        assert!(!properties.contains_key("@timestamp"));
+        assert!(properties.contains_key("host"));
+        let process = properties["process"]["properties"].as_object().unwrap();
+        assert!(!process.contains_key("command_line"));
+        assert!(process.contains_key("output"));
+
+        /* the records wait until the index is fixed */
+        assert!(matches!(client.import(&history()), Err(BulkError::Retryable(_))));
+        assert!(cluster.lock().unwrap().documents.is_empty());
+        assert_eq!(cluster.lock().unwrap().updates.len(), 3);
+
+        cluster.lock().unwrap().mappings["tracker-old"]["mappings"] = MAPPING.clone();
+        client.import(&history()).unwrap();
+        assert_eq!(cluster.lock().unwrap().documents.len(), 3);
+
+        /* an up to date template is left alone */
+        let client = ESClient::from("127.0.0.1", port as u64, "tracker-%Y").unwrap();
+        assert!(client.setup().unwrap().is_empty());
+        assert_eq!(cluster.lock().unwrap().updates.len(), 3);
+    }
+
+    #[test]
+    fn setup_with_write_only_credentials() {
+        let (port, cluster) = mock_es();
+        cluster.lock().unwrap().write_only = true;
+        let client = ESClient::from("127.0.0.1", port as u64, "tracker-%Y").unwrap();
+
+        let warnings = client.setup().unwrap();
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].starts_with("index template tracker"));
+        assert!(warnings[0].contains("status code 403"));
+        assert!(warnings[1].starts_with("mappings of tracker-* not checked"));
+        assert!(cluster.lock().unwrap().updates.is_empty());
+
+        client.import(&history()).unwrap();
+        assert_eq!(cluster.lock().unwrap().documents.len(), 3);
     }
 }
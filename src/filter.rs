@@ -0,0 +1,285 @@
+use regex::Regex;
+
+use crate::console::session_dir;
+use crate::utils::*;
+
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone)]
+pub struct FilterError(String);
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for FilterError {}
+
+/* what a rule is matched against */
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Field {
+    Cmd,    /* console: command line */
+    Cwd,    /* console: working directory */
+    Host,   /* browser: url host */
+    Scheme, /* browser: url scheme */
+}
+
+impl Field {
+    fn is_console(self) -> bool {
+        self == Field::Cmd || self == Field::Cwd
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Pattern {
+    Glob(glob::Pattern),
+    Regex(Regex),
+}
+
+impl Pattern {
+    fn matches(&self, value: &str) -> bool {
+        match self {
+            Pattern::Glob(g) => g.matches(value),
+            Pattern::Regex(r) => r.is_match(value),
+        }
+    }
+}
+
+/**
+ * `<field> <pattern>`: field is cmd, cwd, host or scheme, pattern is a glob
+ * or, prefixed by `regex:`, a regex. `glob:` may prefix a glob.
+ */
+#[derive(Clone, Debug)]
+struct Rule {
+    field: Field,
+    pattern: Pattern,
+}
+
+impl Rule {
+    fn parse(rule: &str) -> Result<Rule, FilterError> {
+        let mut parts = rule.trim().splitn(2, char::is_whitespace);
+        let field = match parts.next() {
+            Some("cmd") => Field::Cmd,
+            Some("cwd") => Field::Cwd,
+            Some("host") => Field::Host,
+            Some("scheme") => Field::Scheme,
+            _ => {
+                return Err(FilterError(format!(
+                    "bad rule {}: field must be cmd, cwd, host or scheme",
+                    rule
+                )))
+            }
+        };
+
+        let pattern = parts.next().map(|p| p.trim()).unwrap_or("");
+        if pattern.is_empty() {
+            return Err(FilterError(format!("bad rule {}: pattern missing", rule)));
+        }
+        let pattern = if let Some(re) = pattern.strip_prefix("regex:") {
+            Pattern::Regex(Regex::new(re).map_err(|err| {
+                FilterError(format!("bad rule {}: {}", rule, err))
+            })?)
+        } else {
+            let glob = pattern.strip_prefix("glob:").unwrap_or(pattern);
+            Pattern::Glob(glob::Pattern::new(glob).map_err(|err| {
+                FilterError(format!("bad rule {}: {}", rule, err))
+            })?)
+        };
+
+        Ok(Rule { field, pattern })
+    }
+}
+
+/**
+ * Decides which commands and urls are tracked: a record is kept if it
+ * matches no exclude rule and, when there are include rules for its kind
+ * (console or browser), at least one of them. Nothing is kept while the
+ * operator paused tracking.
+ */
+#[derive(Clone, Debug, Default)]
+pub struct Filter {
+    include: Vec<Rule>,
+    exclude: Vec<Rule>,
+    pause_file: Option<PathBuf>, /* exists while tracking is paused */
+}
+
+impl Filter {
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Filter, FilterError> {
+        let parse = |rules: &[String]| -> Result<Vec<Rule>, FilterError> {
+            rules.iter().map(|r| Rule::parse(r)).collect()
+        };
+
+        Ok(Filter {
+            include: parse(include)?,
+            exclude: parse(exclude)?,
+            pause_file: None,
+        })
+    }
+
+    /**
+     * Check that `rule` is a valid include/exclude rule.
+     */
+    pub fn check_rule(rule: &str) -> Result<(), FilterError> {
+        Rule::parse(rule).map(|_| ())
+    }
+
+    /**
+     * Pause tracking while the pause file in the session dir of tracker
+     * `id` exists: it holds the pause start in milliseconds and is read by
+     * the shell hooks too.
+     */
+    pub fn with_pause_file(mut self, id: u32) -> Result<Filter, FilterError> {
+        let dir = session_dir(id).map_err(|err| FilterError(err.to_string()))?;
+        self.pause_file = Some(dir.join("paused"));
+        Ok(self)
+    }
+
+    pub fn pause_file(&self) -> Option<&Path> {
+        self.pause_file.as_deref()
+    }
+
+    pub fn pause(&self) -> Result<(), FilterError> {
+        if let Some(path) = &self.pause_file {
+            fs::write(path, timestamp_now_ms().to_string()).map_err(|err| {
+                FilterError(format!("failed to pause tracking: {}", err))
+            })?;
+        }
+        Ok(())
+    }
+
+    pub fn resume(&self) -> Result<(), FilterError> {
+        if let Some(path) = &self.pause_file {
+            if let Err(err) = fs::remove_file(path) {
+                if err.kind() != std::io::ErrorKind::NotFound {
+                    return Err(FilterError(format!(
+                        "failed to resume tracking: {}",
+                        err
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /**
+     * Start of the pause in milliseconds, None if tracking isn't paused.
+     */
+    pub fn paused_since(&self) -> Option<u64> {
+        let contents = fs::read_to_string(self.pause_file.as_ref()?).ok()?;
+        Some(contents.trim().parse::<u64>().unwrap_or(0))
+    }
+
+    fn keep(&self, console: bool, value: impl Fn(Field) -> Option<String>) -> bool {
+        let matches = |rule: &Rule| match value(rule.field) {
+            Some(v) => rule.pattern.matches(&v),
+            None => false,
+        };
+
+        if self.exclude.iter().any(matches) {
+            return false;
+        }
+        let mut include = self.include.iter().filter(|r| r.field.is_console() == console).peekable();
+        include.peek().is_none() || include.any(matches)
+    }
+
+    /**
+     * Track command `cmd` run in `cwd` at `start` (milliseconds).
+     */
+    pub fn keep_command(&self, cmd: &str, cwd: &str, start: u64) -> bool {
+        if let Some(since) = self.paused_since() {
+            if start >= since {
+                return false;
+            }
+        }
+
+        self.keep(true, |field| match field {
+            Field::Cmd => Some(cmd.to_owned()),
+            Field::Cwd => Some(cwd.to_owned()),
+            _ => None,
+        })
+    }
+
+    /**
+     * Track the visit of `url` at `timestamp` (seconds).
+     */
+    pub fn keep_url(&self, url: &str, timestamp: u64) -> bool {
+        if let Some(since) = self.paused_since() {
+            if timestamp * 1000 >= since {
+                return false;
+            }
+        }
+
+        let parsed = url::Url::parse(url).ok();
+        self.keep(false, |field| match field {
+            Field::Host => parsed.as_ref().and_then(|u| u.host_str()).map(|h| h.to_owned()),
+            Field::Scheme => parsed.as_ref().map(|u| u.scheme().to_owned()),
+            _ => None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn filter(include: &[&str], exclude: &[&str]) -> Filter {
+        let owned = |rules: &[&str]| rules.iter().map(|r| r.to_string()).collect::<Vec<String>>();
+        Filter::new(&owned(include), &owned(exclude)).unwrap()
+    }
+
+    #[test]
+    fn exclude_commands() {
+        let f = filter(&[], &["cmd glob:*vault*", "cwd /home/op/private/**"]);
+        assert!(f.keep_command("ls -la", "/home/op", 0));
+        assert!(!f.keep_command("vault read secret/x", "/home/op", 0));
+        assert!(!f.keep_command("ls", "/home/op/private/notes", 0));
+    }
+
+    #[test]
+    fn include_commands() {
+        let f = filter(&["cmd regex:^(nmap|curl)\\b"], &["cmd *--help*"]);
+        assert!(f.keep_command("nmap -sV 10.0.0.1", "/", 0));
+        assert!(!f.keep_command("nmap --help", "/", 0));
+        assert!(!f.keep_command("ls", "/", 0));
+        /* console rules don't filter urls */
+        assert!(f.keep_url("https://example.com/", 0));
+    }
+
+    #[test]
+    fn filter_urls() {
+        let f = filter(&["scheme http*"], &["host regex:(^|\\.)bank\\.com$"]);
+        assert!(f.keep_url("https://target.local/login", 0));
+        assert!(!f.keep_url("https://www.bank.com/account", 0));
+        assert!(!f.keep_url("about:blank", 0));
+        assert!(f.keep_command("ls", "/", 0));
+    }
+
+    #[test]
+    fn bad_rules() {
+        assert!(Filter::check_rule("cmd ls").is_ok());
+        assert!(Filter::check_rule("user root").is_err());
+        assert!(Filter::check_rule("cmd").is_err());
+        assert!(Filter::check_rule("host regex:(").is_err());
+    }
+
+    #[test]
+    fn pause_and_resume() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut f = filter(&[], &[]);
+        f.pause_file = Some(dir.path().join("paused.1"));
+
+        let now = timestamp_now_ms();
+        f.pause().unwrap();
+        assert!(f.paused_since().is_some());
+        assert!(f.keep_command("before", "/", now - 10_000));
+        assert!(!f.keep_command("during", "/", now + 10_000));
+        assert!(!f.keep_url("https://example.com/", now / 1000 + 10));
+
+        f.resume().unwrap();
+        assert!(f.paused_since().is_none());
+        assert!(f.keep_command("after", "/", now + 10_000));
+    }
+}
@@ -7,8 +7,19 @@ use std::thread;
 pub mod browser;
 pub mod cli;
 pub mod console;
+#[cfg(not(target_os = "windows"))]
+pub mod control;
 pub mod elastic;
+pub mod filter;
 pub mod log;
+#[cfg(not(target_os = "windows"))]
+pub mod nest;
+pub mod redact;
+pub mod session;
+pub mod sink;
+pub mod spool;
+#[cfg(not(target_os = "windows"))]
+pub mod ssh;
 pub mod utils;
 
 
@@ -34,6 +45,10 @@ impl JsonDumper for browser::BrowserHistControl {
                         "@timestamp".to_string(),
                         serde_json::json!(entry.timestamp)
                     );
+                    json_value.insert(
+                        "event.dataset".to_string(),
+                        serde_json::json!("tracker.browser")
+                    );
                     json_value.insert(
                         "url.full".to_string(),
                         serde_json::json!(entry.url)
@@ -93,6 +108,18 @@ impl JsonDumper for console::ConsoleHistControl {
                         "@timestamp".to_string(),
                         serde_json::json!(entry.timestamp)
                     );
+                    json_value.insert(
+                        "event.dataset".to_string(),
+                        serde_json::json!("tracker.console")
+                    );
+                    json_value.insert(
+                        "event.sequence".to_string(),
+                        serde_json::json!(entry.seq)
+                    );
+                    json_value.insert(
+                        "process.parent.pid".to_string(),
+                        serde_json::json!(self.id())
+                    );
                     json_value.insert(
                         "process.user".to_string(),
                         serde_json::json!(entry.user)
@@ -109,6 +136,119 @@ impl JsonDumper for console::ConsoleHistControl {
                         "process.output".to_string(),
                         serde_json::json!(entry.output),
                     );
+                    json_value.insert(
+                        "process.output_raw".to_string(),
+                        serde_json::json!(entry.output_raw),
+                    );
+                    json_value.insert(
+                        "process.output_bytes".to_string(),
+                        serde_json::json!(entry.output_info.bytes),
+                    );
+                    json_value.insert(
+                        "process.output_truncated".to_string(),
+                        serde_json::json!(entry.output_info.truncated),
+                    );
+                    json_value.insert(
+                        "process.output_binary".to_string(),
+                        serde_json::json!(entry.output_info.binary),
+                    );
+                    if let Some(sha256) = &entry.output_info.sha256 {
+                        json_value.insert(
+                            "process.output_sha256".to_string(),
+                            serde_json::json!(sha256),
+                        );
+                    }
+                    json_value.insert(
+                        "process.working_directory".to_string(),
+                        serde_json::json!(entry.cwd),
+                    );
+                    json_value.insert(
+                        "process.start".to_string(),
+                        serde_json::json!(entry.start),
+                    );
+                    json_value.insert(
+                        "process.end".to_string(),
+                        serde_json::json!(entry.end),
+                    );
+                    json_value.insert(
+                        "process.duration_ms".to_string(),
+                        serde_json::json!(entry.duration),
+                    );
+                    json_value.insert(
+                        "process.pid".to_string(),
+                        serde_json::json!(entry.pid),
+                    );
+                    json_value.insert(
+                        "process.shlvl".to_string(),
+                        serde_json::json!(entry.shlvl),
+                    );
+                    json_value.insert(
+                        "process.nesting_depth".to_string(),
+                        serde_json::json!(entry.depth),
+                    );
+                    json_value.insert(
+                        "user.effective.name".to_string(),
+                        serde_json::json!(entry.user),
+                    );
+                    json_value.insert(
+                        "process.tty.name".to_string(),
+                        serde_json::json!(entry.tty),
+                    );
+                    if let Some(mux) = &entry.mux {
+                        json_value.insert(
+                            "tracker.multiplexer.name".to_string(),
+                            serde_json::json!(mux.name),
+                        );
+                        json_value.insert(
+                            "tracker.multiplexer.session".to_string(),
+                            serde_json::json!(mux.session),
+                        );
+                        json_value.insert(
+                            "tracker.multiplexer.window".to_string(),
+                            serde_json::json!(mux.window),
+                        );
+                        json_value.insert(
+                            "tracker.multiplexer.pane".to_string(),
+                            serde_json::json!(mux.pane),
+                        );
+                    }
+                    /* the session stamps the tracker host on the others */
+                    if let Some(host) = &entry.host {
+                        json_value.insert(
+                            "host.name".to_string(),
+                            serde_json::json!(host.name),
+                        );
+                        json_value.insert(
+                            "host.hostname".to_string(),
+                            serde_json::json!(host.name),
+                        );
+                        json_value.insert(
+                            "host.ip".to_string(),
+                            if host.ip.is_empty() {
+                                serde_json::Value::Null
+                            } else {
+                                serde_json::json!(host.ip)
+                            },
+                        );
+                        json_value.insert(
+                            "host.os.type".to_string(),
+                            serde_json::json!(host.os),
+                        );
+                        json_value.insert(
+                            "host.os.kernel".to_string(),
+                            serde_json::json!(host.kernel),
+                        );
+                    }
+                    if let Some((path, offset)) = self.recording_offset(entry) {
+                        json_value.insert(
+                            "tracker.recording.path".to_string(),
+                            serde_json::json!(path.display().to_string()),
+                        );
+                        json_value.insert(
+                            "tracker.recording.offset".to_string(),
+                            serde_json::json!(offset),
+                        );
+                    }
 
                     let json_value = serde_json::to_value(json_value).unwrap();
 
@@ -7,6 +7,7 @@ use std::fs;
 
 enum LogLevel {
     Info,
+    Warning,
     Error
 }
 
@@ -18,6 +19,7 @@ fn log_msg(level: LogLevel, msg: &str) {
 
     let msg = match level {
         LogLevel::Info => format!("[{}]:INFO:{}", now, msg),
+        LogLevel::Warning => format!("[{}]:WARNING:{}", now, msg),
         LogLevel::Error => format!("[{}]:ERROR:{}", now, msg),
     };
     if let Ok(mut file) = fs::OpenOptions::new()
@@ -37,3 +39,7 @@ pub fn log_error(msg: &str) {
 pub fn log_info(msg: &str) {
     log_msg(LogLevel::Info, &msg);
 }
+
+pub fn log_warning(msg: &str) {
+    log_msg(LogLevel::Warning, msg);
+}
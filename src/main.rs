@@ -1,3 +1,6 @@
+use std::path::PathBuf;
+#[cfg(not(target_os = "windows"))]
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time;
@@ -5,96 +8,696 @@ use std::time;
 use tracker::browser::*;
 use tracker::cli::*;
 use tracker::console::*;
+#[cfg(not(target_os = "windows"))]
+use tracker::control::*;
 use tracker::elastic::*;
+use tracker::filter::*;
+#[cfg(not(target_os = "windows"))]
+use tracker::nest::*;
+use tracker::redact::*;
+use tracker::session::*;
+use tracker::sink::*;
+use tracker::spool::*;
+#[cfg(not(target_os = "windows"))]
+use tracker::ssh::*;
 use tracker::utils::*;
 use tracker::*;
 
+#[cfg(not(target_os = "windows"))]
+static STOP: AtomicBool = AtomicBool::new(false);
+
+/**
+ * Events of the control channel waiting for the writer loops, and the
+ * state of the sinks as of the last flush: `trk` never waits for the sinks.
+ */
+#[derive(Default)]
+struct Outbox {
+    events: Mutex<Vec<serde_json::value::Value>>, /* written by the next dump */
+    status: Mutex<Vec<(String, usize, bool)>>,    /* name, pending batches, healthy */
+}
+
+impl Outbox {
+    fn push(&self, event: serde_json::value::Value) {
+        self.events.lock().unwrap().push(event);
+    }
+
+    fn take(&self) -> Vec<serde_json::value::Value> {
+        std::mem::take(&mut *self.events.lock().unwrap())
+    }
+
+    fn update(&self, sink: &Mutex<MultiSink>) {
+        let status = sink.lock().unwrap().status();
+        *self.status.lock().unwrap() = status;
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+extern "C" fn on_stop(_: libc::c_int) {
+    STOP.store(true, Ordering::SeqCst);
+}
+
 fn main() {
+    #[cfg(not(target_os = "windows"))]
+    {
+        let args: Vec<String> = std::env::args().collect();
+        match args.get(1).map(|a| a.as_str()) {
+            // `tracker ctl ...`: talk to the tracker running the current shell
+            Some("ctl") => std::process::exit(run_ctl(&args[1..])),
+            // `tracker attach <pid>`: register a shell to the daemon
+            Some("attach") => std::process::exit(run_attach(&args[1..])),
+            // `tracker wrap <program> ...`: run by the hooks for shells, sudo and su
+            Some("wrap") if args.len() > 2 => {
+                eprintln!("tracker: {}", exec_wrapped(&args[2], &args[3..]));
+                std::process::exit(1);
+            }
+            // `tracker ssh ...`: ssh with the remote shell tracked
+            Some("ssh") => match run_ssh(&args[2..]) {
+                Ok(code) => std::process::exit(code),
+                Err(err) => {
+                    eprintln!("tracker: {}", err);
+                    std::process::exit(255);
+                }
+            },
+            // `tracker nest ...`: a shell nested in a tracked session
+            Some("nest") => {
+                let nest = NestCli::new(&args[1..]).unwrap_or_else(|e| {
+                    eprintln!("tracker: {}", e);
+                    std::process::exit(1);
+                });
+                eprintln!(
+                    "tracker: {}",
+                    exec_nested(nest.shell, &nest.options, &nest.nesting)
+                );
+                std::process::exit(1);
+            }
+            // `tracker daemon ...`: track the shells attaching to it
+            Some("daemon") => {
+                let mut daemon_args = vec!["tracker daemon".to_owned()];
+                daemon_args.extend_from_slice(&args[2..]);
+                let cli = Cli::from_args(&daemon_args).unwrap_or_else(|e| {
+                    eprintln!("[*] ERR: {}", e);
+                    std::process::exit(1);
+                });
+                run_daemon(cli);
+                return;
+            }
+            _ => {}
+        }
+    }
+
     let cli = Cli::new().unwrap_or_else(|e| {
         eprintln!("[*] ERR: {}", e.to_string());
         std::process::exit(1);
     });
+    // the shell shows on stdout, records there would be mixed with it
+    if cli.outputs.iter().any(|output| matches!(output, SinkKind::Stdout)) {
+        eprintln!("[*] ERR: the stdout output is taken by the shell, use file:<path> instead");
+        std::process::exit(1);
+    }
 
-    let username = Arc::new(Mutex::new(whoami()));
-    let ipaddr = Arc::new(Mutex::new(ip_get_addr(&cli.interface).to_string()));
+    let session = new_session(&cli);
+    let mut sink = open_sinks(&cli);
+    let redactor = open_redactor(&cli);
 
-    let es_config = ESConfig::new(cli.host, cli.port, &cli.index);
-    let es_client = ESClient::new(es_config);
+    // commands and urls to track, paused by `trk pause` in the shell
+    let pid = std::process::id();
+    let filter = Filter::new(&cli.include, &cli.exclude)
+        .and_then(|f| f.with_pause_file(pid))
+        .unwrap_or_else(|e| {
+            eprintln!("[*] ERR: {}", e);
+            std::process::exit(1);
+        });
 
-    // browser history control
-    let mut b_history = None;
-    if !cli.browser.is_none() {
-        b_history = Some(
-            BrowserHistControl::new(cli.browser.unwrap(), BrowserHistFrom::Now).unwrap_or_else(
-                |e| {
-                    eprintln!("[*] ERR: {}", e.to_string());
-                    std::process::exit(1);
-                },
-            ),
-        );
-        println!("[*] Browser history db founded correctly.")
-    }
+    let b_history = open_browser(&cli, &filter);
 
     // shell history control
     let mut c_history = ConsoleHistControl::new();
+    c_history.set_max_output(cli.max_output);
+    c_history.set_filter(filter.clone());
 
-    // start bash
-    let pid = std::process::id();
-    println!("[*] Starting /bin/bash.");
-    println!("ATTENTION: Output is redirected.");
-    let console = start_console(pid, &mut c_history).unwrap_or_else(|err| {
-        eprintln!("[*] ERR: {}.", err.to_string());
+    // start the shell
+    println!("[*] Starting {}.", cli.shell);
+    println!("[*] Recording the terminal.");
+    let recording = PathBuf::from(format!("{}/{}.cast", TRACKER_RECORDINGS_DIR, session.id()));
+    let console = start_console(pid, cli.shell, Some(recording), &mut c_history).unwrap_or_else(|err| {
+        eprintln!("[*] ERR: {}.", err);
         std::process::exit(1);
     });
 
+    // replay records spooled by previous runs
+    if let Err(err) = sink.flush() {
+        log::log_error(&format!("failed to replay spool: {}", err));
+    }
+
+    let sink = Arc::new(Mutex::new(sink));
+    write_records(&sink, &[session.start_event()]);
+    let session = Arc::new(Mutex::new(session));
+    let outbox = Arc::new(Outbox::default());
+    outbox.update(&sink);
+
+    // control channel for `trk` in the shell
+    #[cfg(not(target_os = "windows"))]
+    let _control = start_control(pid, filter, &redactor, &session, &outbox);
+
     // threads: dump browser and console history
     let mut runner = Runner::new();
-    let es_client = Arc::new(Mutex::new(es_client));
-    if !b_history.is_none() {
-        let async_esclient = Arc::clone(&es_client);
-        let async_username = Arc::clone(&username);
-        let async_ipaddr = Arc::clone(&ipaddr);
-        let mut dumper = b_history.unwrap();
-        runner.start_loop(move || {
-            if let Some(mut records) = dumper.dump() {
-                update_records(&mut records, &async_username.lock().unwrap(), &async_ipaddr.lock().unwrap());
-                let _ = async_esclient.lock().unwrap().bulk_import(records);
+    if let Some(dumper) = b_history {
+        start_browser_loop(&mut runner, dumper, &redactor, &session, &sink);
+    }
+
+    let c_history = Arc::new(Mutex::new(c_history));
+    let async_history = Arc::clone(&c_history);
+    let async_sink = Arc::clone(&sink);
+    let async_session = Arc::clone(&session);
+    let async_redactor = redactor.clone();
+    let async_outbox = Arc::clone(&outbox);
+    runner.start_loop(move || {
+        write_records(&async_sink, &async_outbox.take());
+        dump_console(&async_history, &async_redactor, &async_session, &async_sink);
+        thread::sleep(time::Duration::from_millis(500));
+    });
+
+    start_flush_loop(&mut runner, &sink, &outbox);
+
+    // main thread: wait shell
+    let clean = match console.join() {
+        Ok(Ok(_)) => true,
+        Ok(Err(err)) => {
+            eprintln!("[*] ERR: {}.", err);
+            false
+        }
+        Err(_) => {
+            eprintln!("[*] ERR: failed to wait console.");
+            false
+        }
+    };
+
+    // stop the threads, read the last commands, then close the session
+    drop(runner);
+    write_records(&sink, &outbox.take());
+    dump_console(&c_history, &redactor, &session, &sink);
+    let end_event = session.lock().unwrap().end_event();
+    write_records(&sink, &[end_event]);
+    if let Err(err) = flush_sinks(&sink) {
+        log::log_error(&format!("failed to send records, kept in the spool: {}", err));
+    }
+
+    // nothing is left of a session that ended well
+    #[cfg(not(target_os = "windows"))]
+    drop(_control);
+    if clean {
+        if let Err(err) = c_history.lock().unwrap().remove_session_dir() {
+            log::log_error(&err.to_string());
+        }
+    }
+    println!("[*] Exit...");
+}
+
+/**
+ * Write the new commands of the tracked shell.
+ */
+fn dump_console(
+    history: &Mutex<ConsoleHistControl>,
+    redactor: &Redactor,
+    session: &Mutex<Session>,
+    sink: &Mutex<MultiSink>,
+) {
+    let records = history.lock().unwrap().dump();
+    if let Some(mut records) = records {
+        redactor.redact_records(&mut records);
+        session.lock().unwrap().stamp(&mut records);
+        write_records(sink, &records);
+    }
+}
+
+fn new_session(cli: &Cli) -> Session {
+    Session::new(
+        &whoami(),
+        &ip_get_addr(&cli.interface).to_string(),
+        cli.engagement.clone(),
+        cli.tags.clone(),
+    )
+}
+
+fn open_sinks(cli: &Cli) -> MultiSink {
+    let mut sink = MultiSink::new();
+    for output in &cli.outputs {
+        match output {
+            SinkKind::Elastic => {
+                let es_config = ESConfig::new(&cli.host, cli.port, &cli.index)
+                    .with_scheme(cli.scheme)
+                    .with_data_stream(cli.data_stream)
+                    .with_ilm_policy(cli.ilm_policy.clone())
+                    .with_nodes(cli.nodes.clone())
+                    .with_cloud_id(cli.cloud_id.clone())
+                    .with_auth(cli.auth.clone())
+                    .with_ca_cert(cli.ca_cert.as_ref())
+                    .with_verify_certs(cli.verify_certs);
+                let es_client = ESClient::new(es_config).unwrap_or_else(|e| {
+                    eprintln!("[*] ERR: {}", e);
+                    std::process::exit(1);
+                });
+                let warnings = es_client.setup().unwrap_or_else(|e| {
+                    eprintln!("[*] ERR: {}", e);
+                    std::process::exit(1);
+                });
+                for warning in warnings {
+                    eprintln!("[*] WARN: {}", warning);
+                }
+                let spool = Spool::new(es_client, SPOOL_DIR).unwrap_or_else(|e| {
+                    eprintln!("[*] ERR: {}", e);
+                    std::process::exit(1);
+                });
+                sink.push(Box::new(spool));
             }
-            thread::sleep(time::Duration::from_millis(500));
-        });
+            SinkKind::File(path) => sink.push(Box::new(FileSink::new(path))),
+            SinkKind::Stdout => sink.push(Box::new(StdoutSink::new())),
+        }
     }
+    sink
+}
+
+/* secrets are redacted from the records before they reach the sinks */
+fn open_redactor(cli: &Cli) -> Redactor {
+    Redactor::new(&cli.redact).unwrap_or_else(|e| {
+        eprintln!("[*] ERR: {}", e);
+        std::process::exit(1);
+    })
+}
+
+fn open_browser(cli: &Cli, filter: &Filter) -> Option<BrowserHistControl> {
+    let browser = cli.browser.clone()?;
+    let mut b_history =
+        BrowserHistControl::new(browser, BrowserHistFrom::Now).unwrap_or_else(|e| {
+            eprintln!("[*] ERR: {}", e);
+            std::process::exit(1);
+        });
+    b_history.set_filter(filter.clone());
+    println!("[*] Browser history db founded correctly.");
+    Some(b_history)
+}
 
-    let async_esclient = Arc::clone(&es_client);
-    let async_username = Arc::clone(&username);
-    let async_ipaddr = Arc::clone(&ipaddr);
+fn start_browser_loop(
+    runner: &mut Runner,
+    mut dumper: BrowserHistControl,
+    redactor: &Redactor,
+    session: &Arc<Mutex<Session>>,
+    sink: &Arc<Mutex<MultiSink>>,
+) {
+    let async_sink = Arc::clone(sink);
+    let async_session = Arc::clone(session);
+    let async_redactor = redactor.clone();
     runner.start_loop(move || {
-        if let Some(mut records) = c_history.dump() {
-            update_records(&mut records, &async_username.lock().unwrap(), &async_ipaddr.lock().unwrap());
-            let _ = async_esclient.lock().unwrap().bulk_import(records);
+        if let Some(mut records) = dumper.dump() {
+            async_redactor.redact_records(&mut records);
+            async_session.lock().unwrap().stamp(&mut records);
+            write_records(&async_sink, &records);
         }
         thread::sleep(time::Duration::from_millis(500));
     });
+}
 
-    // main thread: wait shell
-    console.join().expect("[*] ERR: failed to wait console");
+/* send the spooled records, retrying while the sinks are down */
+fn start_flush_loop(runner: &mut Runner, sink: &Arc<Mutex<MultiSink>>, outbox: &Arc<Outbox>) {
+    let async_sink = Arc::clone(sink);
+    let async_outbox = Arc::clone(outbox);
+    runner.start_loop(move || {
+        let _ = flush_sinks(&async_sink);
+        async_outbox.update(&async_sink);
+        thread::sleep(time::Duration::from_millis(1000));
+    });
+}
+
+/* the records are sent once the sinks are unlocked, the writers don't wait */
+fn flush_sinks(sink: &Mutex<MultiSink>) -> Result<(), SinkError> {
+    let flush = sink.lock().unwrap().take_flush();
+    match flush {
+        Some(flush) => flush(),
+        None => Ok(()),
+    }
+}
+
+/**
+ * Control channel for `trk` in the shell of tracker `id`.
+ */
+#[cfg(not(target_os = "windows"))]
+fn start_control(
+    id: u32,
+    filter: Filter,
+    redactor: &Redactor,
+    session: &Arc<Mutex<Session>>,
+    outbox: &Arc<Outbox>,
+) -> Option<ControlServer> {
+    let ctl_outbox = Arc::clone(outbox);
+    let ctl_session = Arc::clone(session);
+    let ctl_redactor = redactor.clone();
+    socket_path(id)
+        .and_then(|path| {
+            ControlServer::start(path, move |request| {
+                handle_control(request, &filter, &ctl_redactor, &ctl_session, &ctl_outbox)
+            })
+        })
+        .map_err(|err| log::log_error(&err.to_string()))
+        .ok()
+}
+
+/**
+ * A shell attached to tracker daemon, tracked as a session of its own.
+ */
+#[cfg(not(target_os = "windows"))]
+struct Attached {
+    pid: u32,
+    history: ConsoleHistControl,
+    session: Arc<Mutex<Session>>,
+    _control: Option<ControlServer>, /* `trk` in the shell */
+}
+
+/**
+ * `tracker daemon`: own the sinks and the browser history, and track every
+ * shell attaching to the daemon socket until SIGINT or SIGTERM.
+ */
+#[cfg(not(target_os = "windows"))]
+fn run_daemon(cli: Cli) {
+    let session = new_session(&cli);
+    let mut sink = open_sinks(&cli);
+    let redactor = open_redactor(&cli);
+    let filter = Filter::new(&cli.include, &cli.exclude).unwrap_or_else(|e| {
+        eprintln!("[*] ERR: {}", e);
+        std::process::exit(1);
+    });
+    let b_history = open_browser(&cli, &filter);
+
+    // replay records spooled by previous runs
+    if let Err(err) = sink.flush() {
+        log::log_error(&format!("failed to replay spool: {}", err));
+    }
+
+    let sink = Arc::new(Mutex::new(sink));
+    let session = Arc::new(Mutex::new(session));
+    let shells: Arc<Mutex<Vec<Attached>>> = Arc::new(Mutex::new(Vec::new()));
+    let outbox = Arc::new(Outbox::default());
+    outbox.update(&sink);
+
+    // shells attach from their hooks
+    let async_shells = Arc::clone(&shells);
+    let async_outbox = Arc::clone(&outbox);
+    let async_redactor = redactor.clone();
+    let daemon = daemon_socket_path()
+        .and_then(|path| {
+            ControlServer::start(path, move |request| match request {
+                Request::Attach(pid) => {
+                    attach_shell(pid, &cli, &filter, &async_redactor, &async_shells, &async_outbox)
+                }
+                _ => Response::error("not supported by tracker daemon: use trk in the shell"),
+            })
+        })
+        .unwrap_or_else(|e| {
+            eprintln!("[*] ERR: {}", e);
+            std::process::exit(1);
+        });
+    println!("[*] Waiting for shells on {}.", daemon.path().display());
+
+    let start_event = session.lock().unwrap().start_event();
+    write_records(&sink, &[start_event]);
+
+    // threads: dump browser history and the history of every shell
+    let mut runner = Runner::new();
+    if let Some(dumper) = b_history {
+        start_browser_loop(&mut runner, dumper, &redactor, &session, &sink);
+    }
+
+    let async_shells = Arc::clone(&shells);
+    let async_sink = Arc::clone(&sink);
+    let async_redactor = redactor.clone();
+    let async_outbox = Arc::clone(&outbox);
+    runner.start_loop(move || {
+        /* taken with the shells held, the start of a session comes before its commands */
+        let mut shells = async_shells.lock().unwrap();
+        let mut records = async_outbox.take();
+        records.extend(dump_shells(&mut shells, &async_redactor, false));
+        drop(shells);
+        write_records(&async_sink, &records);
+        thread::sleep(time::Duration::from_millis(500));
+    });
+
+    start_flush_loop(&mut runner, &sink, &outbox);
+
+    // main thread: wait to be stopped
+    unsafe {
+        libc::signal(libc::SIGINT, on_stop as *const () as libc::sighandler_t);
+        libc::signal(libc::SIGTERM, on_stop as *const () as libc::sighandler_t);
+    }
+    while !STOP.load(Ordering::SeqCst) {
+        thread::sleep(time::Duration::from_millis(200));
+    }
+
+    // stop the threads, then close the sessions of the shells still attached
+    drop(runner);
+    drop(daemon);
+    let mut records = outbox.take();
+    records.extend(dump_shells(&mut shells.lock().unwrap(), &redactor, true));
+    records.push(session.lock().unwrap().end_event());
+    write_records(&sink, &records);
+    if let Err(err) = flush_sinks(&sink) {
+        log::log_error(&format!("failed to send records, kept in the spool: {}", err));
+    }
     println!("[*] Exit...");
 }
 
+/**
+ * Start tracking shell `pid`, with its own session dir and session.
+ */
+#[cfg(not(target_os = "windows"))]
+fn attach_shell(
+    pid: u32,
+    cli: &Cli,
+    filter: &Filter,
+    redactor: &Redactor,
+    shells: &Mutex<Vec<Attached>>,
+    outbox: &Arc<Outbox>,
+) -> Response {
+    let mut shells = shells.lock().unwrap();
+    if let Some(shell) = shells.iter().find(|s| s.pid == pid) {
+        /* the same shell again, after an `exec` */
+        let session = shell.session.lock().unwrap();
+        return Response::ok(&format!("attached, session {}", session.id()));
+    }
 
-fn update_records(records: &mut Vec<serde_json::value::Value>, username: &str, ip: &str) {
-    for record in records {
-        match record {
-            serde_json::value::Value::Object(o) => {
-                o.insert(
-                    "user.name".to_string(),
-                    serde_json::json!(username),
-                );
-                o.insert(
-                    "host.ip".to_string(),
-                    serde_json::json!(ip)
-                );
-            },
-            _ => {}
+    let filter = match filter.clone().with_pause_file(pid) {
+        Ok(filter) => filter,
+        Err(err) => return Response::error(&err.to_string()),
+    };
+    let mut history = ConsoleHistControl::new();
+    history.set_max_output(cli.max_output);
+    history.set_filter(filter.clone());
+    if let Err(err) = history.init(pid) {
+        return Response::error(&err.to_string());
+    }
+    /* read from now on, even before the hooks of the shell touch it */
+    if let Err(err) = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(history.session_dir().join("hist.log"))
+    {
+        return Response::error(&format!("failed to create histlog: {}", err));
+    }
+
+    let session = new_session(cli);
+    let message = format!("attached, session {}", session.id());
+    outbox.push(session.start_event());
+    let session = Arc::new(Mutex::new(session));
+    let control = start_control(pid, filter, redactor, &session, outbox);
+
+    shells.push(Attached {
+        pid,
+        history,
+        session,
+        _control: control,
+    });
+    Response::ok(&message)
+}
+
+/**
+ * New records of the attached shells, each stamped with its own session.
+ * The shells that exited, or all of them if `detach`, are closed and removed.
+ */
+#[cfg(not(target_os = "windows"))]
+fn dump_shells(
+    shells: &mut Vec<Attached>,
+    redactor: &Redactor,
+    detach: bool,
+) -> Vec<serde_json::value::Value> {
+    let mut all = Vec::new();
+    let mut i = 0;
+    while i < shells.len() {
+        /* checked before reading, so the last commands of the shell are kept */
+        let gone = detach || !process_alive(shells[i].pid);
+
+        let shell = &mut shells[i];
+        if let Some(mut records) = shell.history.dump() {
+            redactor.redact_records(&mut records);
+            shell.session.lock().unwrap().stamp(&mut records);
+            all.extend(records);
         }
+
+        if gone {
+            all.push(shell.session.lock().unwrap().end_event());
+            /* a detached shell may still be running */
+            if !detach {
+                if let Err(err) = shell.history.remove_session_dir() {
+                    log::log_error(&err.to_string());
+                }
+            }
+            shells.remove(i);
+        } else {
+            i += 1;
+        }
+    }
+    all
+}
+
+/**
+ * Apply a command of the operator and answer it.
+ */
+#[cfg(not(target_os = "windows"))]
+fn handle_control(
+    request: Request,
+    filter: &Filter,
+    redactor: &Redactor,
+    session: &Mutex<Session>,
+    outbox: &Outbox,
+) -> Response {
+    match request {
+        Request::Pause | Request::Resume => {
+            let pause = request == Request::Pause;
+            let result = if pause { filter.pause() } else { filter.resume() };
+            match result {
+                Ok(()) => {
+                    let event = session.lock().unwrap().pause_event(pause);
+                    outbox.push(event);
+                    Response::ok(if pause { "tracking paused" } else { "tracking resumed" })
+                }
+                Err(err) => Response::error(&err.to_string()),
+            }
+        }
+        /* typed by the operator, they may carry secrets as commands do */
+        Request::Tag(tag) => {
+            let (tag, _) = redactor.redact(&tag);
+            let event = session.lock().unwrap().add_tag(&tag);
+            outbox.push(event);
+            Response::ok(&format!("tagged {}", tag))
+        }
+        Request::Note(text) => {
+            let (text, _) = redactor.redact(&text);
+            let event = session.lock().unwrap().note_event(&text);
+            outbox.push(event);
+            Response::ok("note recorded")
+        }
+        Request::Status => {
+            let (id, tags) = {
+                let session = session.lock().unwrap();
+                (session.id().to_owned(), session.tags().clone())
+            };
+            let mut status = vec![
+                format!("session: {}", id),
+                format!(
+                    "tracking: {}",
+                    if filter.paused_since().is_some() { "paused" } else { "on" }
+                ),
+            ];
+            if !tags.is_empty() {
+                status.push(format!("tags: {}", tags.join(", ")));
+            }
+            for (name, pending, healthy) in outbox.status.lock().unwrap().iter() {
+                status.push(format!(
+                    "sink {}: {}, {} batches queued",
+                    name,
+                    if *healthy { "ok" } else { "failing" },
+                    pending
+                ));
+            }
+            Response::ok(&status.join("\n"))
+        }
+        Request::Attach(_) => Response::error("the shell is already tracked"),
+    }
+}
+
+/**
+ * `tracker ctl`: send a command to the running tracker, print its answer.
+ */
+#[cfg(not(target_os = "windows"))]
+fn run_ctl(args: &[String]) -> i32 {
+    let ctl = CtlCli::new(args).unwrap_or_else(|e| {
+        eprintln!("tracker: {}", e);
+        std::process::exit(1);
+    });
+
+    match socket_path(ctl.id).and_then(|path| send(path, &ctl.request)) {
+        Ok(response) if response.ok => {
+            println!("tracker: {}", response.message);
+            0
+        }
+        Ok(response) => {
+            eprintln!("tracker: {}", response.message);
+            1
+        }
+        Err(err) => {
+            eprintln!("tracker: {}", err);
+            1
+        }
+    }
+}
+
+/**
+ * `tracker attach`: register a shell to tracker daemon. Fails if the daemon
+ * isn't running, so that the hooks leave the shell alone. With `--exec`,
+ * run the shell anyway.
+ */
+#[cfg(not(target_os = "windows"))]
+fn run_attach(args: &[String]) -> i32 {
+    let attach = AttachCli::new(args).unwrap_or_else(|e| {
+        eprintln!("tracker: {}", e);
+        std::process::exit(1);
+    });
+
+    let request = Request::Attach(attach.pid);
+    let result = daemon_socket_path().and_then(|path| send(path, &request));
+
+    // become the shell, tracked or not
+    if let Some(shell) = attach.exec {
+        let id = match result {
+            Ok(response) if response.ok => Some(attach.pid),
+            Ok(response) => {
+                eprintln!("tracker: not tracked: {}", response.message);
+                None
+            }
+            Err(_) => None,
+        };
+        eprintln!("tracker: {}", exec_shell(shell, id));
+        return 1;
+    }
+
+    match result {
+        Ok(response) if response.ok => {
+            println!("tracker: {}", response.message);
+            0
+        }
+        Ok(response) => {
+            eprintln!("tracker: {}", response.message);
+            1
+        }
+        Err(err) => {
+            eprintln!("tracker: {}", err);
+            1
+        }
+    }
+}
+
+fn write_records(sink: &Mutex<MultiSink>, records: &[serde_json::value::Value]) {
+    if records.is_empty() {
+        return;
+    }
+    if let Err(err) = sink.lock().unwrap().write(records) {
+        log::log_error(&format!("failed to write records: {}", err));
     }
 }
@@ -0,0 +1,735 @@
+use crate::console::{init_file, session_dir, shell_command, Shell};
+
+use std::ffi::{CString, OsStr};
+use std::fmt;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+#[derive(Debug, Clone)]
+pub struct NestError(String);
+
+impl fmt::Display for NestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for NestError {}
+
+/* sudo options followed by a value */
+const SUDO_VALUE_OPTS: &str = "CDgprRtTuU";
+const SUDO_VALUE_LONG: [&str; 11] = [
+    "--close-from",
+    "--chdir",
+    "--group",
+    "--host",
+    "--prompt",
+    "--role",
+    "--chroot",
+    "--type",
+    "--command-timeout",
+    "--user",
+    "--other-user",
+];
+/* sudo options that don't run a command */
+const SUDO_NO_COMMAND: [(char, &str); 7] = [
+    ('e', "--edit"),
+    ('h', "--help"),
+    ('k', "--reset-timestamp"),
+    ('K', "--remove-timestamp"),
+    ('l', "--list"),
+    ('v', "--validate"),
+    ('V', "--version"),
+];
+
+/* su options followed by a value */
+const SU_VALUE_OPTS: &str = "cgGsw";
+const SU_VALUE_LONG: [&str; 5] = ["--command", "--group", "--supp-group", "--shell", "--whitelist-environment"];
+
+/* ssh options followed by a value */
+const SSH_VALUE_OPTS: &str = "BbcDEeFIiJLlmOopQRSWw";
+/* ssh options that don't start a remote shell on a terminal */
+const SSH_NO_SHELL: &str = "fGNOQTVW";
+
+/**
+ * The tracked session a nested shell joins. It is passed from the tracked
+ * shell to `tracker nest`, through sudo and su when the user changes.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Nesting {
+    pub id: u32,              /* TRACKER_ID of the session */
+    pub session_dir: PathBuf, /* with the histlog of the session */
+    pub depth: u32,           /* nesting depth of the new shell, 0 being the tracked one */
+    pub home: PathBuf,        /* home of the operator, with the tracker hooks */
+    pub pty: Option<String>,  /* nonce of the markers of the tracker pty the shells run on */
+}
+
+impl Nesting {
+    /**
+     * Nesting of a shell started from the tracked shell, from its environment.
+     */
+    pub fn from_env() -> Result<Nesting, NestError> {
+        let id = std::env::var("TRACKER_ID")
+            .ok()
+            .and_then(|id| id.parse::<u32>().ok())
+            .ok_or_else(|| NestError("not in a tracked shell: TRACKER_ID not valid".to_owned()))?;
+        let session_dir = match std::env::var_os("TRACKER_SESSION_DIR") {
+            Some(dir) => PathBuf::from(dir),
+            None => session_dir(id).map_err(|err| NestError(err.to_string()))?,
+        };
+        let depth = std::env::var("TRACKER_DEPTH")
+            .ok()
+            .and_then(|d| d.parse::<u32>().ok())
+            .unwrap_or(0);
+        /* set in nested shells, where HOME may be the one of another user */
+        let home = match std::env::var_os("TRACKER_HOME") {
+            Some(home) => PathBuf::from(home),
+            None => dirs::home_dir().ok_or_else(|| NestError("home dir not found".to_owned()))?,
+        };
+
+        Ok(Nesting {
+            id,
+            session_dir,
+            depth: depth + 1,
+            home,
+            pty: match std::env::var_os("TRACKER_PTY") {
+                Some(_) => std::env::var("TRACKER_NONCE").ok(),
+                None => None,
+            },
+        })
+    }
+
+    /**
+     * `tracker nest` arguments passing the nesting on.
+     */
+    pub fn args(&self) -> Vec<String> {
+        let mut args = vec![
+            "nest".to_owned(),
+            "--id".to_owned(),
+            self.id.to_string(),
+            "--session-dir".to_owned(),
+            self.session_dir.display().to_string(),
+            "--depth".to_owned(),
+            self.depth.to_string(),
+            "--home".to_owned(),
+            self.home.display().to_string(),
+        ];
+        if let Some(nonce) = &self.pty {
+            args.push("--pty".to_owned());
+            args.push(nonce.clone());
+        }
+        args
+    }
+}
+
+/**
+ * What a command run from the tracked shell starts.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub enum Launch {
+    Shell(Shell, Vec<String>),                   /* `bash -x`: the shell and its options */
+    Sudo(Vec<String>, Option<Shell>),            /* `sudo -i`, `sudo bash`: sudo options, the shell if named */
+    Su(Vec<String>, Vec<String>, Option<Shell>), /* `[sudo] su - root`: command before su, su arguments, shell set by -s */
+    Ssh(Vec<String>),                            /* `ssh -p 2222 host`: ssh arguments */
+    Other,                                       /* not an interactive shell: run as is */
+}
+
+fn shell_name(program: &str) -> Option<Shell> {
+    Shell::parse(Path::new(program).file_name()?.to_str()?)
+}
+
+/**
+ * Options of shell `shell` that keep it interactive, None if it runs a
+ * command or a script instead (or is a bash login shell, which doesn't
+ * read the tracker rcfile).
+ */
+fn interactive_options(shell: Shell, args: &[String]) -> Option<Vec<String>> {
+    for arg in args {
+        if arg.starts_with("--") {
+            let not_interactive: &[&str] = match shell {
+                Shell::Bash => &["--login", "--rcfile", "--init-file", "--norc", "--version", "--help"],
+                Shell::Zsh => &["--version", "--help"],
+                Shell::Fish => &["--command", "--init-command", "--no-execute", "--version", "--help"],
+            };
+            if arg == "--" || not_interactive.iter().any(|o| arg.split('=').next() == Some(o)) {
+                return None;
+            }
+        } else if let Some(flags) = arg.strip_prefix('-') {
+            let not_interactive = match shell {
+                Shell::Bash => "clsDO",
+                Shell::Zsh => "cs",
+                Shell::Fish => "cnvhC",
+            };
+            if flags.is_empty() || flags.chars().any(|c| not_interactive.contains(c)) {
+                return None;
+            }
+        } else {
+            /* a script, or the value of an option */
+            return None;
+        }
+    }
+    Some(args.to_vec())
+}
+
+/**
+ * Split command line `args` of a program taking options `value_opts` (and
+ * long options `value_long`) followed by a value: its options, then the rest.
+ */
+fn split_options(args: &[String], value_opts: &str, value_long: &[&str]) -> (Vec<String>, Vec<String>) {
+    let mut i = 0;
+    while i < args.len() {
+        let arg = &args[i];
+        if arg == "--" {
+            i += 1;
+            break;
+        } else if arg.starts_with("--") {
+            if value_long.contains(&arg.as_str()) {
+                i += 1;
+            }
+        } else if arg.starts_with('-') && arg.len() > 1 {
+            /* a cluster like -iu: the value follows its option, or is the next argument */
+            let flags = &arg[1..];
+            if let Some(pos) = flags.find(|c| value_opts.contains(c)) {
+                if pos == flags.len() - 1 {
+                    i += 1;
+                }
+            }
+        } else {
+            break;
+        }
+        i += 1;
+    }
+    let i = std::cmp::min(i, args.len());
+    (args[..i].to_vec(), args[i..].to_vec())
+}
+
+/**
+ * Option `short` or `long` is in `options`, also within a cluster like -Ei
+ * (up to an option taking a value, in `value_opts`).
+ */
+fn has_flag(options: &[String], short: char, long: &str, value_opts: &str) -> bool {
+    options.iter().any(|o| {
+        if o.starts_with("--") {
+            o == long || o.starts_with(&format!("{}=", long))
+        } else if let Some(flags) = o.strip_prefix('-') {
+            for c in flags.chars() {
+                if c == short {
+                    return true;
+                }
+                if value_opts.contains(c) {
+                    break;
+                }
+            }
+            false
+        } else {
+            false
+        }
+    })
+}
+
+/**
+ * `su` arguments: interactive if there's no command, at most a user.
+ */
+fn parse_su(prefix: Vec<String>, args: &[String]) -> Launch {
+    let (options, rest) = split_options(args, SU_VALUE_OPTS, &SU_VALUE_LONG);
+    /* `-` alone is the short form of --login */
+    let rest: Vec<&String> = rest.iter().filter(|a| a.as_str() != "-").collect();
+    if rest.len() > 1 || has_flag(&options, 'c', "--command", SU_VALUE_OPTS) {
+        return Launch::Other;
+    }
+
+    let mut shell = None;
+    for (i, option) in options.iter().enumerate() {
+        let value = if option == "-s" || option == "--shell" {
+            options.get(i + 1).map(|v| v.as_str())
+        } else {
+            option.strip_prefix("--shell=")
+        };
+        if let Some(value) = value {
+            match shell_name(value) {
+                Some(s) => shell = Some(s),
+                None => return Launch::Other,
+            }
+        }
+    }
+    Launch::Su(prefix, args.to_vec(), shell)
+}
+
+/**
+ * What `program args` starts, typed in the tracked shell.
+ */
+pub fn parse_launch(program: &str, args: &[String]) -> Launch {
+    if let Some(shell) = shell_name(program) {
+        return match interactive_options(shell, args) {
+            Some(options) => Launch::Shell(shell, options),
+            None => Launch::Other,
+        };
+    }
+
+    match program {
+        "su" => parse_su(vec![], args),
+        "ssh" => {
+            /* a remote command, or anything but a destination after the options */
+            let (options, rest) = split_options(args, SSH_VALUE_OPTS, &[]);
+            if rest.len() != 1 || SSH_NO_SHELL.chars().any(|c| has_flag(&options, c, "", SSH_VALUE_OPTS)) {
+                return Launch::Other;
+            }
+            Launch::Ssh(args.to_vec())
+        }
+        "sudo" => {
+            let (options, command) = split_options(args, SUDO_VALUE_OPTS, &SUDO_VALUE_LONG);
+            let flag = |short, long| has_flag(&options, short, long, SUDO_VALUE_OPTS);
+            if SUDO_NO_COMMAND.iter().any(|&(short, long)| flag(short, long)) {
+                return Launch::Other;
+            }
+
+            match command.split_first() {
+                None if flag('i', "--login") || flag('s', "--shell") => {
+                    Launch::Sudo(options, None)
+                }
+                Some((program, rest)) if Path::new(program).file_name() == Some(OsStr::new("su")) => {
+                    let mut prefix = vec!["sudo".to_owned()];
+                    prefix.extend(options);
+                    prefix.push(program.clone());
+                    parse_su(prefix, rest)
+                }
+                Some((program, [])) => match shell_name(program) {
+                    Some(shell) => Launch::Sudo(options, Some(shell)),
+                    None => Launch::Other,
+                },
+                _ => Launch::Other,
+            }
+        }
+        _ => Launch::Other,
+    }
+}
+
+/* `arg` quoted for sh */
+pub(crate) fn quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+/**
+ * Options of sudo `options` choosing the user and group to run as, the
+ * only ones `sudo -l` takes along with a command.
+ */
+fn sudo_target(options: &[String]) -> Vec<String> {
+    let mut target = vec![];
+    let mut i = 0;
+    while i < options.len() {
+        let option = &options[i];
+        if option.starts_with("--") {
+            let keep = ["--user", "--group", "--host"].contains(&option.split('=').next().unwrap_or(""));
+            if keep {
+                target.push(option.clone());
+            }
+            if SUDO_VALUE_LONG.contains(&option.as_str()) {
+                i += 1;
+                if keep {
+                    target.extend(options.get(i).cloned());
+                }
+            }
+        } else if let Some(flags) = option.strip_prefix('-') {
+            /* a cluster like -iu bob, or -iubob */
+            if let Some(pos) = flags.find(|c| SUDO_VALUE_OPTS.contains(c)) {
+                let value = if pos == flags.len() - 1 {
+                    i += 1;
+                    options.get(i).cloned().unwrap_or_default()
+                } else {
+                    flags[pos + 1..].to_owned()
+                };
+                let flag = &flags[pos..pos + 1];
+                if flag == "u" || flag == "g" {
+                    target.push(format!("-{}", flag));
+                    target.push(value);
+                }
+            }
+        }
+        i += 1;
+    }
+    target
+}
+
+/**
+ * sudo lets the user run `line`, `sudo <options> <command>`, as told by
+ * `sudo -l`: restricted rules may allow a shell as typed, not as tracked.
+ * sudo may ask for the password, as the command would.
+ */
+pub fn sudo_allows(line: &[String]) -> bool {
+    let (options, command) = split_options(&line[1..], SUDO_VALUE_OPTS, &SUDO_VALUE_LONG);
+    let status = Command::new(&line[0])
+        .arg("-l")
+        .args(sudo_target(&options))
+        .args(command)
+        .stdout(Stdio::null())
+        .status();
+    matches!(status, Ok(status) if status.success())
+}
+
+/**
+ * bash script run by sudo or su as the new user: bash with the tracker
+ * hooks `rc` passed inline, as for `tracker ssh`, recording to the session
+ * of `nesting`, as a `login` shell for `sudo -i` and `su -`. The hooks are
+ * read from the operator's home as the operator: whoever can change them
+ * runs commands as the new user, as with the operator's own rc files. A
+ * user who can't write the session histlog gets `shell` untracked.
+ */
+fn hooked_script(rc: &str, nesting: &Nesting, shell: Option<Shell>, login: bool) -> String {
+    let pty = match &nesting.pty {
+        Some(nonce) => format!("export TRACKER_PTY=1 TRACKER_NONCE={}; ", quote(nonce)),
+        None => "unset TRACKER_PTY TRACKER_NONCE; ".to_owned(),
+    };
+    /* the hooks read the profile, bash --rcfile can't be a login shell */
+    let login = if login { "export TRACKER_LOGIN=1; " } else { "unset TRACKER_LOGIN; " };
+    let fallback = match shell {
+        Some(shell) => shell.name(),
+        None => "\"${SHELL:-/bin/sh}\"",
+    };
+    format!(
+        "export TRACKER_ID={} TRACKER_SESSION_DIR={} TRACKER_DEPTH={} TRACKER_HOME={}; \
+         unset TRACKER_ATTACHED TRACKER_BIN; {}{}\
+         if [ -w \"$TRACKER_SESSION_DIR/hist.log\" ]; then exec bash --rcfile <(printf '%s' {}) -i; fi; \
+         echo 'tracker: the shell is not tracked' >&2; exec {}",
+        nesting.id,
+        quote(&nesting.session_dir.display().to_string()),
+        nesting.depth,
+        quote(&nesting.home.display().to_string()),
+        pty,
+        login,
+        quote(rc),
+        fallback
+    )
+}
+
+/**
+ * Command line running the sudo or su of `launch` with the bash hooks `rc`,
+ * None for another shell, whose hooks can't be passed inline.
+ */
+fn hooked_line(launch: Launch, nesting: &Nesting, rc: &str) -> Option<Vec<String>> {
+    match launch {
+        Launch::Sudo(options, shell) if matches!(shell, None | Some(Shell::Bash)) => {
+            let login = has_flag(&options, 'i', "--login", SUDO_VALUE_OPTS);
+            let script = hooked_script(rc, nesting, shell, login);
+            let mut line = vec!["sudo".to_owned()];
+            line.extend(options);
+            line.extend(vec!["bash".to_owned(), "-c".to_owned(), script]);
+            Some(line)
+        }
+        Launch::Su(prefix, su_args, shell) if matches!(shell, None | Some(Shell::Bash)) => {
+            let (options, rest) = split_options(&su_args, SU_VALUE_OPTS, &SU_VALUE_LONG);
+            let login = rest.iter().any(|a| a == "-") || has_flag(&options, 'l', "--login", SU_VALUE_OPTS);
+            let script = hooked_script(rc, nesting, shell, login);
+            /* su -c runs the login shell of the user, which may not be bash */
+            let mut line = if prefix.is_empty() { vec!["su".to_owned()] } else { prefix };
+            line.extend(su_args);
+            line.push("-c".to_owned());
+            line.push(format!("bash -c {}", quote(&script)));
+            Some(line)
+        }
+        _ => None,
+    }
+}
+
+/**
+ * Command running `program args` from the tracked shell: a shell it starts
+ * joins the tracked session by `nesting`, through `bin nest`, or with the
+ * bash hooks `rc` for sudo and su. What sudo doesn't allow, by
+ * `sudo_allows`, runs as typed.
+ */
+pub fn wrap_command(
+    program: &str,
+    args: &[String],
+    nesting: &Nesting,
+    bin: &Path,
+    rc: Option<&str>,
+    sudo_allows: &dyn Fn(&[String]) -> bool,
+) -> Command {
+    match parse_launch(program, args) {
+        Launch::Shell(shell, options) => {
+            let mut command = Command::new(bin);
+            command.args(nesting.args()).args(["--shell", shell.name(), "--"]).args(options);
+            command
+        }
+        Launch::Ssh(ssh_args) => {
+            let mut command = Command::new(bin);
+            command.arg("ssh").args(ssh_args);
+            command
+        }
+        launch => {
+            let line = match rc.and_then(|rc| hooked_line(launch, nesting, rc)) {
+                Some(line) if line[0] != "sudo" || sudo_allows(&line) => line,
+                _ => {
+                    let mut line = vec![program.to_owned()];
+                    line.extend_from_slice(args);
+                    line
+                }
+            };
+            let mut command = Command::new(&line[0]);
+            command.args(&line[1..]);
+            command
+        }
+    }
+}
+
+/**
+ * `tracker wrap`: run `program args` in place of the tracked shell
+ * function of the same name. Returns only on error.
+ */
+pub fn exec_wrapped(program: &str, args: &[String]) -> NestError {
+    let command = match (Nesting::from_env(), std::env::current_exe()) {
+        (Ok(nesting), Ok(bin)) => {
+            /* read as the operator, run as the new user */
+            let rc = init_file(Shell::Bash, Some(&nesting.home))
+                .ok()
+                .and_then(|init| std::fs::read_to_string(init).ok());
+            wrap_command(program, args, &nesting, &bin, rc.as_deref(), &sudo_allows)
+        }
+        _ => {
+            let mut command = Command::new(program);
+            command.args(args);
+            command
+        }
+    };
+    let mut command = command;
+    let err = command.exec();
+    NestError(format!("failed to run {}: {}", program, err))
+}
+
+fn writable(path: &Path) -> bool {
+    match CString::new(path.as_os_str().as_bytes()) {
+        Ok(path) => unsafe { libc::access(path.as_ptr(), libc::W_OK) == 0 },
+        Err(_) => false,
+    }
+}
+
+/**
+ * `tracker nest`: become `shell` with the tracker hooks of the operator,
+ * recording to the session of `nesting`. A user who can't write to the
+ * session gets a plain shell. Returns only on error.
+ */
+pub fn exec_nested(shell: Shell, options: &[String], nesting: &Nesting) -> NestError {
+    let histlog = nesting.session_dir.join("hist.log");
+    let hooked = if writable(&histlog) {
+        shell_command(shell, Some(&nesting.home)).map_err(|err| err.to_string())
+    } else {
+        Err(format!("{} not writable", histlog.display()))
+    };
+
+    let mut command = match hooked {
+        Ok(mut command) => {
+            command.env("TRACKER_ID", nesting.id.to_string());
+            command.env("TRACKER_SESSION_DIR", &nesting.session_dir);
+            command.env("TRACKER_DEPTH", nesting.depth.to_string());
+            command.env("TRACKER_HOME", &nesting.home);
+            /* recorded by the tracked session, not attached on its own */
+            command.env_remove("TRACKER_ATTACHED");
+            if let Some(nonce) = &nesting.pty {
+                command.env("TRACKER_PTY", "1");
+                command.env("TRACKER_NONCE", nonce);
+            } else {
+                command.env_remove("TRACKER_PTY");
+                command.env_remove("TRACKER_NONCE");
+            }
+            command
+        }
+        Err(err) => {
+            eprintln!("tracker: {}, the shell is not tracked", err);
+            Command::new(shell.name())
+        }
+    };
+    command.args(options);
+
+    let err = command.exec();
+    NestError(format!("failed to run {}: {}", shell, err))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn launch(command: &str) -> Launch {
+        let args: Vec<String> = command.split_whitespace().map(|a| a.to_owned()).collect();
+        parse_launch(&args[0], &args[1..])
+    }
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn nested_shells() {
+        assert_eq!(launch("bash"), Launch::Shell(Shell::Bash, vec![]));
+        assert_eq!(launch("/bin/zsh -x"), Launch::Shell(Shell::Zsh, strings(&["-x"])));
+        assert_eq!(launch("fish --private"), Launch::Shell(Shell::Fish, strings(&["--private"])));
+        assert_eq!(launch("bash script.sh"), Launch::Other);
+        assert_eq!(launch("bash -c ls"), Launch::Other);
+        assert_eq!(launch("bash -xc ls"), Launch::Other);
+        assert_eq!(launch("bash --login"), Launch::Other);
+        assert_eq!(launch("fish -c ls"), Launch::Other);
+        assert_eq!(launch("ls -la"), Launch::Other);
+    }
+
+    #[test]
+    fn sudo_shells() {
+        assert_eq!(launch("sudo -i"), Launch::Sudo(strings(&["-i"]), None));
+        assert_eq!(launch("sudo -u bob -s"), Launch::Sudo(strings(&["-u", "bob", "-s"]), None));
+        assert_eq!(launch("sudo -iu bob"), Launch::Sudo(strings(&["-iu", "bob"]), None));
+        assert_eq!(launch("sudo -Es"), Launch::Sudo(strings(&["-Es"]), None));
+        assert_eq!(launch("sudo --login"), Launch::Sudo(strings(&["--login"]), None));
+        assert_eq!(launch("sudo bash"), Launch::Sudo(vec![], Some(Shell::Bash)));
+        assert_eq!(launch("sudo -E /usr/bin/zsh"), Launch::Sudo(strings(&["-E"]), Some(Shell::Zsh)));
+        assert_eq!(
+            launch("sudo su -"),
+            Launch::Su(strings(&["sudo", "su"]), strings(&["-"]), None)
+        );
+        assert_eq!(launch("sudo"), Launch::Other);
+        assert_eq!(launch("sudo -l"), Launch::Other);
+        assert_eq!(launch("sudo -h"), Launch::Other);
+        assert_eq!(launch("sudo -u root id"), Launch::Other);
+        assert_eq!(launch("sudo bash -c id"), Launch::Other);
+        assert_eq!(launch("sudo -i id"), Launch::Other);
+    }
+
+    #[test]
+    fn su_shells() {
+        assert_eq!(launch("su"), Launch::Su(vec![], vec![], None));
+        assert_eq!(launch("su - root"), Launch::Su(vec![], strings(&["-", "root"]), None));
+        assert_eq!(
+            launch("su -s /bin/zsh bob"),
+            Launch::Su(vec![], strings(&["-s", "/bin/zsh", "bob"]), Some(Shell::Zsh))
+        );
+        assert_eq!(launch("su -c id"), Launch::Other);
+        assert_eq!(launch("su root -c id"), Launch::Other);
+        assert_eq!(launch("su -s /bin/sh"), Launch::Other);
+    }
+
+    #[test]
+    fn ssh_shells() {
+        assert_eq!(launch("ssh jump"), Launch::Ssh(strings(&["jump"])));
+        assert_eq!(
+            launch("ssh -p 2222 -A op@10.0.0.1"),
+            Launch::Ssh(strings(&["-p", "2222", "-A", "op@10.0.0.1"]))
+        );
+        assert_eq!(launch("ssh jump id"), Launch::Other);
+        assert_eq!(launch("ssh -N -L 8080:localhost:80 jump"), Launch::Other);
+        assert_eq!(launch("ssh -fN jump"), Launch::Other);
+        assert_eq!(launch("ssh -T git@github.com"), Launch::Other);
+        assert_eq!(launch("ssh -V"), Launch::Other);
+    }
+
+    #[test]
+    fn wrapped_commands() {
+        let nesting = Nesting {
+            id: 42,
+            session_dir: PathBuf::from("/home/op/.tracker/sessions/42"),
+            depth: 1,
+            home: PathBuf::from("/home/op"),
+            pty: Some("f00d".to_owned()),
+        };
+        let bin = Path::new("/usr/bin/tracker");
+        let line = |command: Command| {
+            let mut line = vec![command.get_program().to_string_lossy().to_string()];
+            line.extend(command.get_args().map(|a| a.to_string_lossy().to_string()));
+            line
+        };
+        let command = |c: &str| {
+            let args: Vec<String> = c.split_whitespace().map(|a| a.to_owned()).collect();
+            line(wrap_command(&args[0], &args[1..], &nesting, bin, Some("rc"), &|_| true))
+        };
+        let script = |login: &str, fallback: &str| {
+            format!(
+                "export TRACKER_ID=42 TRACKER_SESSION_DIR='/home/op/.tracker/sessions/42' TRACKER_DEPTH=1 \
+                 TRACKER_HOME='/home/op'; unset TRACKER_ATTACHED TRACKER_BIN; \
+                 export TRACKER_PTY=1 TRACKER_NONCE='f00d'; {}; \
+                 if [ -w \"$TRACKER_SESSION_DIR/hist.log\" ]; then exec bash --rcfile <(printf '%s' 'rc') -i; fi; \
+                 echo 'tracker: the shell is not tracked' >&2; exec {}",
+                login, fallback
+            )
+        };
+        let login = "export TRACKER_LOGIN=1";
+        let no_login = "unset TRACKER_LOGIN";
+
+        assert_eq!(
+            command("bash -x"),
+            strings(&[
+                "/usr/bin/tracker", "nest", "--id", "42", "--session-dir", "/home/op/.tracker/sessions/42",
+                "--depth", "1", "--home", "/home/op", "--pty", "f00d", "--shell", "bash", "--", "-x",
+            ])
+        );
+        /* sudo -i and su - start login shells, the hooks read the profile */
+        assert_eq!(
+            command("sudo -i"),
+            vec!["sudo".to_owned(), "-i".to_owned(), "bash".to_owned(), "-c".to_owned(),
+                 script(login, "\"${SHELL:-/bin/sh}\"")]
+        );
+        assert_eq!(
+            command("sudo -s"),
+            vec!["sudo".to_owned(), "-s".to_owned(), "bash".to_owned(), "-c".to_owned(),
+                 script(no_login, "\"${SHELL:-/bin/sh}\"")]
+        );
+        assert_eq!(
+            command("sudo bash"),
+            vec!["sudo".to_owned(), "bash".to_owned(), "-c".to_owned(), script(no_login, "bash")]
+        );
+        assert_eq!(
+            command("su - bob"),
+            vec!["su".to_owned(), "-".to_owned(), "bob".to_owned(), "-c".to_owned(),
+                 format!("bash -c {}", quote(&script(login, "\"${SHELL:-/bin/sh}\"")))]
+        );
+        assert_eq!(command("su -l bob")[4], format!("bash -c {}", quote(&script(login, "\"${SHELL:-/bin/sh}\""))));
+        assert_eq!(command("su bob")[3], format!("bash -c {}", quote(&script(no_login, "\"${SHELL:-/bin/sh}\""))));
+        /* no inline hooks for zsh and fish */
+        assert_eq!(command("su -s /bin/zsh bob"), strings(&["su", "-s", "/bin/zsh", "bob"]));
+        assert_eq!(command("sudo zsh"), strings(&["sudo", "zsh"]));
+        assert_eq!(command("sudo -u root id"), strings(&["sudo", "-u", "root", "id"]));
+        assert_eq!(quote("it's"), r#"'it'\''s'"#);
+    }
+
+    #[test]
+    fn sudo_not_allowed() {
+        let nesting = Nesting {
+            id: 42,
+            session_dir: PathBuf::from("/home/op/.tracker/sessions/42"),
+            depth: 1,
+            home: PathBuf::from("/home/op"),
+            pty: None,
+        };
+        let bin = Path::new("/usr/bin/tracker");
+        let asked = std::cell::RefCell::new(vec![]);
+        let refuse = |line: &[String]| {
+            asked.borrow_mut().push(line[..3].to_vec());
+            false
+        };
+        let command = |c: &str, rc: Option<&str>| {
+            let args: Vec<String> = c.split_whitespace().map(|a| a.to_owned()).collect();
+            let command = wrap_command(&args[0], &args[1..], &nesting, bin, rc, &refuse);
+            let mut line = vec![command.get_program().to_string_lossy().to_string()];
+            line.extend(command.get_args().map(|a| a.to_string_lossy().to_string()));
+            line
+        };
+
+        /* run as typed */
+        assert_eq!(command("sudo -iu bob", Some("rc")), strings(&["sudo", "-iu", "bob"]));
+        assert_eq!(command("sudo su -", Some("rc")), strings(&["sudo", "su", "-"]));
+        assert_eq!(
+            asked.borrow().clone(),
+            vec![strings(&["sudo", "-iu", "bob"]), strings(&["sudo", "su", "-"])]
+        );
+        /* su without sudo is not asked about, nothing runs without the hooks */
+        assert_eq!(command("su -", Some("rc"))[..3], strings(&["su", "-", "-c"])[..]);
+        assert_eq!(command("sudo -s", None), strings(&["sudo", "-s"]));
+        assert_eq!(asked.borrow().len(), 2);
+    }
+
+    #[test]
+    fn sudo_list_options() {
+        let target = |options: &[&str]| sudo_target(&strings(options));
+        assert_eq!(target(&["-iu", "bob", "-E"]), strings(&["-u", "bob"]));
+        assert_eq!(target(&["-ubob", "-C", "3", "-g", "wheel"]), strings(&["-u", "bob", "-g", "wheel"]));
+        assert_eq!(target(&["--user=bob", "--chdir", "/tmp", "--group", "wheel"]),
+                   strings(&["--user=bob", "--group", "wheel"]));
+        assert_eq!(target(&["-s", "-p", "pw:"]), Vec::<String>::new());
+    }
+}
@@ -0,0 +1,238 @@
+use crate::utils::*;
+
+/**
+ * A tracker run: identifies the records it produces and the machine they
+ * come from.
+ */
+#[derive(Clone, Debug)]
+pub struct Session {
+    id: String,                 /* random UUID */
+    start: u64,                 /* timestamp of the session start */
+    end: Option<u64>,           /* timestamp of the session end, None while running */
+    username: String,           /* operator */
+    ip: String,                 /* address of the tracked interface */
+    hostname: String,
+    os: String,
+    kernel: String,
+    version: String,            /* tracker version */
+    engagement: Option<String>, /* operator-supplied engagement label */
+    tags: Vec<String>,          /* operator-supplied labels */
+    events: u64,                /* operator events emitted so far */
+}
+
+impl Session {
+    pub fn new(username: &str, ip: &str, engagement: Option<String>, tags: Vec<String>) -> Session {
+        Session {
+            id: uuid::Uuid::new_v4().to_string(),
+            start: timestamp_now(),
+            end: None,
+            username: username.to_owned(),
+            ip: ip.to_owned(),
+            hostname: hostname(),
+            os: std::env::consts::OS.to_owned(),
+            kernel: kernel_version(),
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            engagement,
+            tags,
+            events: 0,
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn tags(&self) -> &Vec<String> {
+        &self.tags
+    }
+
+    pub fn engagement(&self) -> Option<&String> {
+        self.engagement.as_ref()
+    }
+
+    /**
+     * Add session and host metadata to every record in `records`.
+     * Fields already set by the record are left untouched.
+     */
+    pub fn stamp(&self, records: &mut Vec<serde_json::Value>) {
+        for record in records {
+            if let serde_json::Value::Object(o) = record {
+                for (key, value) in self.fields() {
+                    o.entry(key).or_insert(value);
+                }
+            }
+        }
+    }
+
+    fn fields(&self) -> Vec<(String, serde_json::Value)> {
+        let mut fields = vec![
+            ("tracker.session.id".to_string(), serde_json::json!(self.id)),
+            ("tracker.session.start".to_string(), serde_json::json!(self.start)),
+            ("tracker.version".to_string(), serde_json::json!(self.version)),
+            ("user.name".to_string(), serde_json::json!(self.username)),
+            ("host.ip".to_string(), serde_json::json!(self.ip)),
+            ("host.name".to_string(), serde_json::json!(self.hostname)),
+            ("host.hostname".to_string(), serde_json::json!(self.hostname)),
+            ("host.os.type".to_string(), serde_json::json!(self.os)),
+            ("host.os.kernel".to_string(), serde_json::json!(self.kernel)),
+        ];
+        if let Some(engagement) = &self.engagement {
+            fields.push(("labels.engagement".to_string(), serde_json::json!(engagement)));
+        }
+        if !self.tags.is_empty() {
+            fields.push(("tags".to_string(), serde_json::json!(self.tags)));
+        }
+        fields
+    }
+
+    fn event(&self, action: &str, timestamp: u64) -> serde_json::Value {
+        let mut event = vec![serde_json::json!({
+            "@timestamp": timestamp,
+            "event.dataset": "tracker.session",
+            "event.action": action,
+        })];
+        self.stamp(&mut event);
+        event.pop().unwrap()
+    }
+
+    /* event of an operator action during the session, numbered */
+    fn operator_event(&mut self, action: &str) -> serde_json::Value {
+        let mut event = self.event(action, timestamp_now());
+        event["event.sequence"] = serde_json::json!(self.events);
+        self.events += 1;
+        event
+    }
+
+    /**
+     * Add `tag` to the records stamped from now on and return the event
+     * recording it.
+     */
+    pub fn add_tag(&mut self, tag: &str) -> serde_json::Value {
+        if !self.tags.iter().any(|t| t == tag) {
+            self.tags.push(tag.to_owned());
+        }
+        let mut event = self.operator_event("tag");
+        event["tracker.tag"] = serde_json::json!(tag);
+        event
+    }
+
+    /**
+     * Event recording a note taken by the operator.
+     */
+    pub fn note_event(&mut self, text: &str) -> serde_json::Value {
+        let mut event = self.operator_event("note");
+        event["message"] = serde_json::json!(text);
+        event
+    }
+
+    /**
+     * Event recording that the operator paused or resumed tracking.
+     */
+    pub fn pause_event(&mut self, paused: bool) -> serde_json::Value {
+        self.operator_event(if paused { "tracking-paused" } else { "tracking-resumed" })
+    }
+
+    /**
+     * Event recording the start of the session.
+     */
+    pub fn start_event(&self) -> serde_json::Value {
+        self.event("session-start", self.start)
+    }
+
+    /**
+     * Close the session and return the event recording its end.
+     */
+    pub fn end_event(&mut self) -> serde_json::Value {
+        let end = timestamp_now();
+        self.end = Some(end);
+
+        let mut event = self.event("session-end", end);
+        event["tracker.session.end"] = serde_json::json!(end);
+        event["event.duration"] = serde_json::json!((end - self.start) * 1_000_000_000);
+        event
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn session() -> Session {
+        Session::new("op", "10.0.0.1", Some("acme".to_owned()), vec!["recon".to_owned()])
+    }
+
+    #[test]
+    fn stamp_keeps_record_fields() {
+        let session = session();
+        let mut records = vec![
+            serde_json::json!({ "process.pid": 1 }),
+            serde_json::json!({ "user.name": "root", "host.ip": "10.0.0.2" }),
+        ];
+        session.stamp(&mut records);
+
+        assert_eq!(records[0]["process.pid"], 1);
+        assert_eq!(records[0]["tracker.session.id"], session.id());
+        assert_eq!(records[0]["user.name"], "op");
+        assert_eq!(records[0]["host.ip"], "10.0.0.1");
+        assert_eq!(records[0]["labels.engagement"], "acme");
+        assert_eq!(records[0]["tags"], serde_json::json!(["recon"]));
+
+        /* set by the record: a command run as root, a remote host */
+        assert_eq!(records[1]["user.name"], "root");
+        assert_eq!(records[1]["host.ip"], "10.0.0.2");
+        assert_eq!(records[1]["tracker.session.id"], session.id());
+    }
+
+    #[test]
+    fn start_and_end_events() {
+        let mut session = session();
+        session.start -= 5;
+
+        let start = session.start_event();
+        assert_eq!(start["event.action"], "session-start");
+        assert_eq!(start["event.dataset"], "tracker.session");
+        assert_eq!(start["@timestamp"], session.start);
+        assert_eq!(start["tracker.session.start"], session.start);
+        assert!(start.get("tracker.session.end").is_none());
+
+        let end = session.end_event();
+        let timestamp = end["@timestamp"].as_u64().unwrap();
+        assert_eq!(end["event.action"], "session-end");
+        assert_eq!(end["tracker.session.id"], start["tracker.session.id"]);
+        assert_eq!(end["tracker.session.end"], timestamp);
+        assert_eq!(session.end, Some(timestamp));
+        assert!(timestamp >= session.start + 5);
+        assert_eq!(end["event.duration"], (timestamp - session.start) * 1_000_000_000);
+    }
+
+    #[test]
+    fn operator_events_numbered() {
+        let mut session = session();
+        let events = [
+            session.pause_event(true),
+            session.note_event("found creds"),
+            session.pause_event(false),
+            session.add_tag("exploit"),
+            session.add_tag("exploit"),
+        ];
+
+        let actions: Vec<&str> = events.iter().map(|e| e["event.action"].as_str().unwrap()).collect();
+        assert_eq!(
+            actions,
+            vec!["tracking-paused", "note", "tracking-resumed", "tag", "tag"]
+        );
+        for (i, event) in events.iter().enumerate() {
+            assert_eq!(event["event.sequence"], i);
+            assert_eq!(event["tracker.session.id"], session.id());
+        }
+        assert_eq!(events[1]["message"], "found creds");
+        assert_eq!(events[3]["tracker.tag"], "exploit");
+        /* stamped with the new tag already, added once */
+        assert_eq!(events[3]["tags"], serde_json::json!(["recon", "exploit"]));
+        assert_eq!(session.tags(), &vec!["recon".to_owned(), "exploit".to_owned()]);
+
+        /* the session events are not numbered */
+        assert!(session.start_event().get("event.sequence").is_none());
+        assert!(session.end_event().get("event.sequence").is_none());
+    }
+}
@@ -0,0 +1,405 @@
+use std::fmt;
+use std::fs;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+use crate::elastic::{BulkError, ESClient};
+use crate::log;
+use crate::utils::{path_expand, timestamp_now};
+
+#[cfg(not(target_os = "windows"))]
+pub const DEAD_LETTER: &str = "~/.tracker/deadletter.ndjson";
+#[cfg(target_os = "windows")]
+pub const DEAD_LETTER: &str = "~\\AppData\\Local\\tracker\\deadletter.ndjson";
+
+#[derive(Clone, Debug)]
+pub struct SinkError(String, bool); /* message, records refused for good */
+
+impl fmt::Display for SinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for SinkError {}
+
+impl SinkError {
+    pub fn new(msg: String) -> SinkError {
+        SinkError(msg, false)
+    }
+
+    /**
+     * The sink will refuse the same records again: retrying is useless.
+     */
+    pub fn permanent(msg: String) -> SinkError {
+        SinkError(msg, true)
+    }
+
+    pub fn is_permanent(&self) -> bool {
+        self.1
+    }
+}
+
+/**
+ * Append `records`, refused by a sink with `status` for `reason`, to the
+ * dead letter file `path` (DEAD_LETTER if None), where they can be sent
+ * again by hand.
+ */
+pub fn dead_letter(
+    path: Option<&Path>,
+    records: &[serde_json::Value],
+    status: u16,
+    reason: &str,
+) {
+    log::log_error(&format!("{} records rejected ({}): {}", records.len(), status, reason));
+
+    let mut contents = String::new();
+    for record in records {
+        let entry = serde_json::json!({
+            "@timestamp": timestamp_now(),
+            "status": status,
+            "reason": reason,
+            "record": record,
+        });
+        contents.push_str(&entry.to_string());
+        contents.push('\n');
+    }
+
+    let path = match path {
+        Some(path) => Ok(path.to_path_buf()),
+        None => path_expand(DEAD_LETTER),
+    };
+    let written = path.and_then(|path| {
+        let mut file = fs::OpenOptions::new().create(true).append(true).open(path)?;
+        file.write_all(contents.as_bytes())
+    });
+    if let Err(err) = written {
+        log::log_error(&format!("failed to write dead letter: {}", err));
+    }
+}
+
+/**
+ * Kind of output sink, as selected from the command line or the cfg file.
+ */
+#[derive(Clone, Debug)]
+pub enum SinkKind {
+    Elastic,
+    File(PathBuf),
+    Stdout,
+}
+
+impl SinkKind {
+    /**
+     * Parse an output specification: "es", "stdout" or "file:<path>".
+     */
+    pub fn parse(output: &str) -> Option<SinkKind> {
+        match output {
+            "es" | "elastic" => Some(SinkKind::Elastic),
+            "stdout" => Some(SinkKind::Stdout),
+            _ => {
+                let path = output.strip_prefix("file:")?;
+                if path.is_empty() {
+                    return None;
+                }
+                match path_expand(path) {
+                    Ok(p) => Some(SinkKind::File(p)),
+                    Err(_) => None,
+                }
+            }
+        }
+    }
+}
+
+/**
+ * Delivery of the records buffered by a sink, taken out of it.
+ */
+pub type Flush = Box<dyn FnOnce() -> Result<(), SinkError> + Send>;
+
+/**
+ * Destination of the records dumped by a `JsonDumper`.
+ */
+pub trait OutputSink {
+    fn write(&mut self, records: &[serde_json::Value]) -> Result<(), SinkError>;
+    fn name(&self) -> String;
+
+    /**
+     * Deliver records buffered by the sink, if any.
+     */
+    fn flush(&mut self) -> Result<(), SinkError> {
+        Ok(())
+    }
+
+    /**
+     * Take the delivery of the buffered records out of the sink, to run it
+     * without holding the sink (e.g. its lock) during the I/O. None if
+     * there is nothing to deliver.
+     */
+    fn take_flush(&mut self) -> Option<Flush> {
+        None
+    }
+
+    /**
+     * Batches buffered by the sink, waiting to be delivered.
+     */
+    fn pending(&self) -> usize {
+        0
+    }
+
+    /**
+     * The sink delivered the last records it was given.
+     */
+    fn healthy(&self) -> bool {
+        true
+    }
+}
+
+impl OutputSink for ESClient {
+    fn write(&mut self, records: &[serde_json::Value]) -> Result<(), SinkError> {
+        match self.import(records) {
+            Ok(()) => Ok(()),
+            Err(BulkError::Retryable(err)) => Err(SinkError::new(err.to_string())),
+            Err(BulkError::Permanent(err)) => Err(SinkError::permanent(err.to_string())),
+        }
+    }
+
+    fn name(&self) -> String {
+        "elasticsearch".to_owned()
+    }
+}
+
+/**
+ * Append records as newline delimited JSON to a local file.
+ */
+#[derive(Clone, Debug)]
+pub struct FileSink {
+    path: PathBuf, /* NDJSON file where records are appended */
+}
+
+impl FileSink {
+    pub fn new<P: AsRef<Path>>(path: P) -> FileSink {
+        FileSink {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+}
+
+impl OutputSink for FileSink {
+    fn write(&mut self, records: &[serde_json::Value]) -> Result<(), SinkError> {
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|err| {
+                SinkError::new(format!(
+                    "failed to open {}: {}",
+                    self.path.display(),
+                    err
+                ))
+            })?;
+
+        let mut contents = String::new();
+        for record in records {
+            contents.push_str(&record.to_string());
+            contents.push('\n');
+        }
+
+        file.write_all(contents.as_bytes()).map_err(|err| {
+            SinkError::new(format!(
+                "failed to write {}: {}",
+                self.path.display(),
+                err
+            ))
+        })
+    }
+
+    fn name(&self) -> String {
+        format!("file:{}", self.path.display())
+    }
+}
+
+/**
+ * Print records as newline delimited JSON to stdout.
+ */
+#[derive(Clone, Debug, Default)]
+pub struct StdoutSink;
+
+impl StdoutSink {
+    pub fn new() -> StdoutSink {
+        StdoutSink
+    }
+}
+
+impl OutputSink for StdoutSink {
+    fn write(&mut self, records: &[serde_json::Value]) -> Result<(), SinkError> {
+        let stdout = std::io::stdout();
+        let mut handle = stdout.lock();
+        for record in records {
+            writeln!(handle, "{}", record)
+                .map_err(|err| SinkError::new(format!("failed to write stdout: {}", err)))?;
+        }
+        Ok(())
+    }
+
+    fn name(&self) -> String {
+        "stdout".to_owned()
+    }
+}
+
+/**
+ * Forward records to several sinks at once.
+ * A failing sink does not prevent the others from receiving records.
+ */
+#[derive(Default)]
+pub struct MultiSink {
+    sinks: Vec<Box<dyn OutputSink + Send>>,
+}
+
+impl MultiSink {
+    pub fn new() -> MultiSink {
+        MultiSink { sinks: Vec::new() }
+    }
+
+    pub fn push(&mut self, sink: Box<dyn OutputSink + Send>) {
+        self.sinks.push(sink);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sinks.is_empty()
+    }
+
+    /**
+     * Name, pending batches and health of each sink.
+     */
+    pub fn status(&self) -> Vec<(String, usize, bool)> {
+        self.sinks
+            .iter()
+            .map(|s| (s.name(), s.pending(), s.healthy()))
+            .collect()
+    }
+}
+
+impl OutputSink for MultiSink {
+    fn write(&mut self, records: &[serde_json::Value]) -> Result<(), SinkError> {
+        let mut errors = Vec::new();
+        for sink in &mut self.sinks {
+            if let Err(err) = sink.write(records) {
+                errors.push(format!("{}: {}", sink.name(), err));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(SinkError::new(errors.join("; ")))
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), SinkError> {
+        let mut errors = Vec::new();
+        for sink in &mut self.sinks {
+            if let Err(err) = sink.flush() {
+                errors.push(format!("{}: {}", sink.name(), err));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(SinkError::new(errors.join("; ")))
+        }
+    }
+
+    fn take_flush(&mut self) -> Option<Flush> {
+        let flushes: Vec<(String, Flush)> = self
+            .sinks
+            .iter_mut()
+            .filter_map(|sink| Some((sink.name(), sink.take_flush()?)))
+            .collect();
+        if flushes.is_empty() {
+            return None;
+        }
+
+        Some(Box::new(move || {
+            let mut errors = Vec::new();
+            for (name, flush) in flushes {
+                if let Err(err) = flush() {
+                    errors.push(format!("{}: {}", name, err));
+                }
+            }
+
+            if errors.is_empty() {
+                Ok(())
+            } else {
+                Err(SinkError::new(errors.join("; ")))
+            }
+        }))
+    }
+
+    fn pending(&self) -> usize {
+        self.sinks.iter().map(|s| s.pending()).sum()
+    }
+
+    fn healthy(&self) -> bool {
+        self.sinks.iter().all(|s| s.healthy())
+    }
+
+    fn name(&self) -> String {
+        let names: Vec<String> = self.sinks.iter().map(|s| s.name()).collect();
+        names.join(",")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_sink_specs() {
+        assert!(matches!(SinkKind::parse("es"), Some(SinkKind::Elastic)));
+        assert!(matches!(SinkKind::parse("elastic"), Some(SinkKind::Elastic)));
+        assert!(matches!(SinkKind::parse("stdout"), Some(SinkKind::Stdout)));
+        match SinkKind::parse("file:/var/log/tracker.ndjson") {
+            Some(SinkKind::File(path)) => assert_eq!(path, PathBuf::from("/var/log/tracker.ndjson")),
+            other => panic!("unexpected sink {:?}", other),
+        }
+        match SinkKind::parse("file:~/records.ndjson") {
+            Some(SinkKind::File(path)) => assert_eq!(path, dirs::home_dir().unwrap().join("records.ndjson")),
+            other => panic!("unexpected sink {:?}", other),
+        }
+
+        assert!(SinkKind::parse("").is_none());
+        assert!(SinkKind::parse("file:").is_none());
+        assert!(SinkKind::parse("file").is_none());
+        assert!(SinkKind::parse("files:/tmp/x").is_none());
+        assert!(SinkKind::parse("http://localhost:9200").is_none());
+    }
+
+    #[test]
+    fn file_sink_appends() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("records.ndjson");
+        let mut sink = FileSink::new(&path);
+        sink.write(&[serde_json::json!({"n": 1})]).unwrap();
+        sink.write(&[serde_json::json!({"n": 2}), serde_json::json!({"n": 3})]).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{\"n\":1}\n{\"n\":2}\n{\"n\":3}\n");
+    }
+
+    #[test]
+    fn multi_sink_survives_failing_sink() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("records.ndjson");
+
+        let mut multi = MultiSink::new();
+        /* a directory can't be opened for appending */
+        multi.push(Box::new(FileSink::new(dir.path())));
+        multi.push(Box::new(FileSink::new(&path)));
+
+        let err = multi.write(&[serde_json::json!({"n": 1})]).unwrap_err();
+        assert!(err.to_string().starts_with(&format!("file:{}: ", dir.path().display())));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{\"n\":1}\n");
+
+        multi.write(&[serde_json::json!({"n": 2})]).unwrap_err();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{\"n\":1}\n{\"n\":2}\n");
+        assert_eq!(multi.name(), format!("file:{},file:{}", dir.path().display(), path.display()));
+    }
+}
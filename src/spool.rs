@@ -0,0 +1,471 @@
+use std::fs;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::log;
+use crate::sink::{self, OutputSink, SinkError};
+use crate::utils::*;
+
+#[cfg(not(target_os = "windows"))]
+pub const SPOOL_DIR: &str = "~/.tracker/spool";
+#[cfg(target_os = "windows")]
+pub const SPOOL_DIR: &str = "~\\AppData\\Local\\tracker\\spool";
+
+const SPOOL_EXT: &str = "ndjson";
+const BACKOFF_MIN: u64 = 1; /* seconds */
+const BACKOFF_MAX: u64 = 300; /* seconds */
+
+/**
+ * Write-ahead spool in front of a sink.
+ * Every batch is stored on disk by `write` and sent by `flush`, it is
+ * removed only once the sink accepts it. Pending batches are replayed in
+ * order, with an exponential backoff while the sink keeps failing. A batch
+ * the sink refuses for good is moved to the dead letter file instead.
+ * The spool dir may be shared by several tracker processes: a batch is
+ * locked while it is replayed.
+ */
+pub struct Spool<S: OutputSink> {
+    dir: PathBuf,                  /* where batches are stored */
+    name: String,                  /* name of the sink */
+    seq: u64,                      /* sequence number of the next batch */
+    healthy: Arc<AtomicBool>,      /* the sink accepted the last replay */
+    replay: Arc<Mutex<Replay<S>>>, /* shared with the flushes taken out */
+}
+
+/**
+ * Sink and retry state of a spool, held for the whole replay.
+ */
+struct Replay<S: OutputSink> {
+    sink: S,                      /* sink receiving the spooled batches */
+    dir: PathBuf,                 /* where batches are stored */
+    dead_letter: Option<PathBuf>, /* where refused batches go, DEAD_LETTER if None */
+    healthy: Arc<AtomicBool>,     /* backoff is 0 */
+    backoff: u64,                 /* current retry delay, 0 if the sink is healthy */
+    next_retry: u64,              /* timestamp of the next allowed replay */
+}
+
+/**
+ * Batches stored in the spool `dir`, oldest first.
+ */
+fn batches(dir: &Path) -> Result<Vec<PathBuf>, SinkError> {
+    let entries = fs::read_dir(dir).map_err(|err| {
+        SinkError::new(format!(
+            "failed to read spool dir {}: {}",
+            dir.display(),
+            err
+        ))
+    })?;
+
+    let mut batches: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|ext| ext == SPOOL_EXT))
+        .collect();
+    batches.sort();
+    Ok(batches)
+}
+
+impl<S: OutputSink> Spool<S> {
+    pub fn new<P: AsRef<Path>>(sink: S, dir: P) -> Result<Spool<S>, SinkError> {
+        let dir = path_expand(&dir).map_err(|err| {
+            SinkError::new(format!("spool dir error: {}", err))
+        })?;
+
+        fs::create_dir_all(&dir).map_err(|err| {
+            SinkError::new(format!(
+                "failed to create spool dir {}: {}",
+                dir.display(),
+                err
+            ))
+        })?;
+
+        let name = format!("spool:{}", sink.name());
+        let healthy = Arc::new(AtomicBool::new(true));
+        let replay = Replay {
+            sink,
+            dir: dir.clone(),
+            dead_letter: None,
+            healthy: Arc::clone(&healthy),
+            backoff: 0,
+            next_retry: 0,
+        };
+        Ok(Spool {
+            dir,
+            name,
+            seq: 0,
+            healthy,
+            replay: Arc::new(Mutex::new(replay)),
+        })
+    }
+
+    /**
+     * Write the batches refused by the sink to `path` instead of DEAD_LETTER.
+     */
+    pub fn with_dead_letter<P: AsRef<Path>>(self, path: P) -> Spool<S> {
+        self.replay.lock().unwrap().dead_letter = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /**
+     * Number of batches waiting to be accepted by the sink.
+     */
+    pub fn pending(&self) -> usize {
+        batches(&self.dir).map(|b| b.len()).unwrap_or(0)
+    }
+
+    fn store(&mut self, records: &[serde_json::Value]) -> Result<PathBuf, SinkError> {
+        let name = format!(
+            "{:020}-{:010}-{:010}.{}",
+            timestamp_now(),
+            std::process::id(),
+            self.seq,
+            SPOOL_EXT
+        );
+        self.seq += 1;
+
+        let batch = self.dir.join(&name);
+        let tmp = self.dir.join(format!(".{}.tmp", name));
+
+        let mut contents = String::new();
+        for record in records {
+            contents.push_str(&record.to_string());
+            contents.push('\n');
+        }
+
+        let mut file = fs::File::create(&tmp).map_err(|err| {
+            SinkError::new(format!("failed to create spool batch: {}", err))
+        })?;
+        file.write_all(contents.as_bytes())
+            .and_then(|_| file.sync_all())
+            .and_then(|_| fs::rename(&tmp, &batch))
+            .map_err(|err| {
+                SinkError::new(format!("failed to write spool batch: {}", err))
+            })?;
+
+        Ok(batch)
+    }
+
+    /**
+     * Lock `batch` for replay. None if another process is replaying it or
+     * has already replayed it.
+     */
+    fn lock(batch: &Path) -> Result<Option<fs::File>, SinkError> {
+        let file = match fs::File::open(batch) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => {
+                return Err(SinkError::new(format!(
+                    "failed to open spool batch {}: {}",
+                    batch.display(),
+                    err
+                )))
+            }
+        };
+
+        #[cfg(not(target_os = "windows"))]
+        {
+            use std::os::unix::fs::MetadataExt;
+            use std::os::unix::io::AsRawFd;
+
+            if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+                return Ok(None);
+            }
+            /* removed between the listing and the lock */
+            if file.metadata().map(|m| m.nlink() == 0).unwrap_or(true) {
+                return Ok(None);
+            }
+        }
+
+        Ok(Some(file))
+    }
+
+    fn load(mut file: &fs::File, batch: &Path) -> Result<Vec<serde_json::Value>, SinkError> {
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).map_err(|err| {
+            SinkError::new(format!(
+                "failed to read spool batch {}: {}",
+                batch.display(),
+                err
+            ))
+        })?;
+
+        let mut records = Vec::new();
+        for line in contents.lines() {
+            match serde_json::from_str(line) {
+                Ok(record) => records.push(record),
+                Err(err) => log::log_error(&format!(
+                    "skipped bad record in spool batch {}: {}",
+                    batch.display(),
+                    err
+                )),
+            }
+        }
+        Ok(records)
+    }
+
+}
+
+impl<S: OutputSink> Replay<S> {
+    /**
+     * Send pending batches to the sink, oldest first.
+     * A batch refused for good is dead-lettered and the replay goes on. Stop
+     * at any other failure and wait for the backoff before retrying.
+     */
+    fn replay(&mut self) -> Result<(), SinkError> {
+        if timestamp_now() < self.next_retry {
+            return Ok(());
+        }
+
+        for batch in batches(&self.dir)? {
+            let file = match Spool::<S>::lock(&batch)? {
+                Some(file) => file,
+                /* replayed by another tracker process, keep the order */
+                None => return Ok(()),
+            };
+
+            let records = Spool::<S>::load(&file, &batch)?;
+            if !records.is_empty() {
+                if let Err(err) = self.sink.write(&records) {
+                    if !err.is_permanent() {
+                        self.backoff = match self.backoff {
+                            0 => BACKOFF_MIN,
+                            b => std::cmp::min(b * 2, BACKOFF_MAX),
+                        };
+                        self.next_retry = timestamp_now() + self.backoff;
+                        self.healthy.store(false, Ordering::SeqCst);
+                        return Err(SinkError::new(format!(
+                            "{} (retry in {}s)",
+                            err,
+                            self.backoff
+                        )));
+                    }
+                    sink::dead_letter(self.dead_letter.as_deref(), &records, 0, &err.to_string());
+                }
+            }
+
+            if let Err(err) = fs::remove_file(&batch) {
+                if err.kind() != std::io::ErrorKind::NotFound {
+                    return Err(SinkError::new(format!(
+                        "failed to remove spool batch {}: {}",
+                        batch.display(),
+                        err
+                    )));
+                }
+            }
+        }
+
+        if self.backoff != 0 {
+            log::log_info(&format!("{} is back, spool replayed", self.sink.name()));
+        }
+        self.backoff = 0;
+        self.next_retry = 0;
+        self.healthy.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+impl<S: OutputSink + Send + 'static> OutputSink for Spool<S> {
+    fn write(&mut self, records: &[serde_json::Value]) -> Result<(), SinkError> {
+        self.store(records).map(|_| ())
+    }
+
+    fn flush(&mut self) -> Result<(), SinkError> {
+        self.replay.lock().unwrap().replay()
+    }
+
+    fn take_flush(&mut self) -> Option<sink::Flush> {
+        if self.pending() == 0 {
+            return None;
+        }
+        let replay = Arc::clone(&self.replay);
+        Some(Box::new(move || replay.lock().unwrap().replay()))
+    }
+
+    fn pending(&self) -> usize {
+        Spool::pending(self)
+    }
+
+    fn healthy(&self) -> bool {
+        self.healthy.load(Ordering::SeqCst)
+    }
+
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+
+    /* sink failing with the queued errors, then accepting everything */
+    #[derive(Clone, Default)]
+    struct TestSink {
+        records: Arc<Mutex<Vec<serde_json::Value>>>,
+        errors: Arc<Mutex<Vec<SinkError>>>,
+    }
+
+    impl OutputSink for TestSink {
+        fn write(&mut self, records: &[serde_json::Value]) -> Result<(), SinkError> {
+            let mut errors = self.errors.lock().unwrap();
+            if !errors.is_empty() {
+                return Err(errors.remove(0));
+            }
+            self.records.lock().unwrap().extend_from_slice(records);
+            Ok(())
+        }
+
+        fn name(&self) -> String {
+            "test".to_owned()
+        }
+    }
+
+    fn records(n: u64) -> Vec<serde_json::Value> {
+        (0..n).map(|i| serde_json::json!({ "seq": i })).collect()
+    }
+
+    #[test]
+    fn store_batches() {
+        let dir = tempfile::tempdir().unwrap();
+        let sink = TestSink::default();
+        let mut spool = Spool::new(sink.clone(), dir.path()).unwrap();
+
+        let first = spool.store(&records(2)).unwrap();
+        let second = spool.store(&records(1)).unwrap();
+        assert!(first < second);
+        assert_eq!(batches(dir.path()).unwrap(), vec![first.clone(), second]);
+        assert_eq!(fs::read_to_string(&first).unwrap(), "{\"seq\":0}\n{\"seq\":1}\n");
+
+        spool.flush().unwrap();
+        assert_eq!(spool.pending(), 0);
+        assert_eq!(sink.records.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn replay_after_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        let sink = TestSink::default();
+        sink.errors.lock().unwrap().push(SinkError::new("down".to_owned()));
+        let mut spool = Spool::new(sink.clone(), dir.path()).unwrap();
+
+        spool.write(&records(2)).unwrap();
+        assert!(spool.flush().is_err());
+        assert!(!spool.healthy());
+        assert_eq!(spool.pending(), 1);
+
+        /* kept in order while waiting for the backoff */
+        spool.write(&records(3)).unwrap();
+        spool.flush().unwrap();
+        assert_eq!(spool.pending(), 2);
+        assert!(sink.records.lock().unwrap().is_empty());
+
+        spool.replay.lock().unwrap().next_retry = 0;
+        spool.flush().unwrap();
+        assert!(spool.healthy());
+        assert_eq!(spool.pending(), 0);
+        let seqs: Vec<u64> = sink.records.lock().unwrap().iter()
+            .map(|record| record["seq"].as_u64().unwrap())
+            .collect();
+        assert_eq!(seqs, vec![0, 1, 0, 1, 2]);
+    }
+
+    #[test]
+    fn flush_taken_out() {
+        let dir = tempfile::tempdir().unwrap();
+        let sink = TestSink::default();
+        let mut spool = Spool::new(sink.clone(), dir.path()).unwrap();
+        assert!(spool.take_flush().is_none());
+
+        spool.write(&records(2)).unwrap();
+        assert!(sink.records.lock().unwrap().is_empty());
+        let flush = spool.take_flush().unwrap();
+
+        /* the spool keeps storing batches while they are sent */
+        let sent = thread::spawn(flush);
+        spool.write(&records(1)).unwrap();
+        sent.join().unwrap().unwrap();
+        if let Some(flush) = spool.take_flush() {
+            flush().unwrap();
+        }
+        assert_eq!(spool.pending(), 0);
+        assert_eq!(sink.records.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn backoff() {
+        let dir = tempfile::tempdir().unwrap();
+        let sink = TestSink::default();
+        let mut spool = Spool::new(sink.clone(), dir.path()).unwrap();
+        spool.store(&records(1)).unwrap();
+
+        for expected in &[1, 2, 4, 8] {
+            sink.errors.lock().unwrap().push(SinkError::new("down".to_owned()));
+            spool.replay.lock().unwrap().next_retry = 0;
+            assert!(spool.flush().is_err());
+            let replay = spool.replay.lock().unwrap();
+            assert_eq!(replay.backoff, *expected);
+            assert!(replay.next_retry >= timestamp_now() + expected - 1);
+        }
+
+        spool.replay.lock().unwrap().backoff = BACKOFF_MAX;
+        sink.errors.lock().unwrap().push(SinkError::new("down".to_owned()));
+        spool.replay.lock().unwrap().next_retry = 0;
+        assert!(spool.flush().is_err());
+        assert_eq!(spool.replay.lock().unwrap().backoff, BACKOFF_MAX);
+    }
+
+    #[test]
+    fn permanent_failure_dead_lettered() {
+        let dir = tempfile::tempdir().unwrap();
+        let dead_letter = dir.path().join("deadletter.ndjson");
+        let sink = TestSink::default();
+        sink.errors.lock().unwrap().push(SinkError::permanent("refused".to_owned()));
+        let mut spool = Spool::new(sink.clone(), dir.path().join("spool"))
+            .unwrap()
+            .with_dead_letter(&dead_letter);
+
+        spool.store(&records(2)).unwrap();
+        spool.store(&records(1)).unwrap();
+        spool.flush().unwrap();
+
+        /* the next batch is not blocked */
+        assert!(spool.healthy());
+        assert_eq!(spool.pending(), 0);
+        assert_eq!(sink.records.lock().unwrap().len(), 1);
+
+        let entries: Vec<serde_json::Value> = fs::read_to_string(&dead_letter)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0]["reason"], "refused");
+        assert_eq!(entries[1]["record"]["seq"], 1);
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    #[test]
+    fn locked_batch_skipped() {
+        use std::os::unix::io::AsRawFd;
+
+        let dir = tempfile::tempdir().unwrap();
+        let sink = TestSink::default();
+        let mut spool = Spool::new(sink.clone(), dir.path()).unwrap();
+        let batch = spool.store(&records(1)).unwrap();
+
+        /* another process replaying the batch */
+        let other = fs::File::open(&batch).unwrap();
+        assert_eq!(unsafe { libc::flock(other.as_raw_fd(), libc::LOCK_EX) }, 0);
+        spool.flush().unwrap();
+        assert_eq!(spool.pending(), 1);
+        assert!(sink.records.lock().unwrap().is_empty());
+
+        drop(other);
+        spool.flush().unwrap();
+        assert_eq!(spool.pending(), 0);
+        assert_eq!(sink.records.lock().unwrap().len(), 1);
+    }
+}
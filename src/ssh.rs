@@ -0,0 +1,127 @@
+use crate::console::output::DEFAULT_MAX_OUTPUT;
+use crate::console::pty::{nonce, Capture, Pty};
+use crate::console::{init_file, Shell};
+use crate::nest::{parse_launch, quote, Launch, Nesting};
+
+use std::fmt;
+use std::fs;
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+
+#[derive(Debug, Clone)]
+pub struct SshError(String);
+
+impl fmt::Display for SshError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for SshError {}
+
+/**
+ * Command run by ssh on the remote host: bash with the tracker hooks `rc`,
+ * passed inline and sending its records through the terminal to the session
+ * of `nesting`. The nonce of the markers is asked for on the terminal, with
+ * echo off, and read from the last line ending with one: lines typed before
+ * are dropped. Nothing is written to the host but a temporary session dir,
+ * removed when the shell exits. A host without bash gets an untracked shell.
+ */
+pub fn remote_command(rc: &str, nesting: &Nesting) -> String {
+    let shell = format!(
+        "stty -echo 2> /dev/null; printf '\\033]6973;N\\007'; \
+         while IFS= read -r -t 10 line && [[ ! $line =~ ([0-9a-f]{{32}})$ ]]; do :; done; \
+         stty echo 2> /dev/null; \
+         export TRACKER_REMOTE=1 TRACKER_PTY=1 TRACKER_NONCE=\"${{BASH_REMATCH[1]}}\" \
+         TRACKER_ID={} TRACKER_DEPTH={}; \
+         TRACKER_SESSION_DIR=\"$(mktemp -d)\" && export TRACKER_SESSION_DIR && \
+         exec bash --rcfile <(printf '%s' {}) -i",
+        nesting.id,
+        nesting.depth,
+        quote(rc)
+    );
+    format!(
+        "if command -v bash > /dev/null 2>&1; then exec bash -c {}; fi; \
+         echo 'tracker: bash not found, the shell is not tracked' >&2; exec \"${{SHELL:-/bin/sh}}\" -l",
+        quote(&shell)
+    )
+}
+
+/**
+ * `tracker ssh`: run ssh with `args`. Run from a tracked shell to a remote
+ * shell, the remote commands are recorded to the same session, with their
+ * output; anything else is run as is. Returns the ssh exit code.
+ */
+pub fn run_ssh(args: &[String]) -> Result<i32, SshError> {
+    let nesting = match (parse_launch("ssh", args), Nesting::from_env()) {
+        (Launch::Ssh(_), Ok(nesting)) => nesting,
+        _ => {
+            let err = Command::new("ssh").args(args).exec();
+            return Err(SshError(format!("failed to run ssh: {}", err)));
+        }
+    };
+
+    let rc = init_file(Shell::Bash, Some(&nesting.home))
+        .map_err(|err| SshError(err.to_string()))
+        .and_then(|init| {
+            fs::read_to_string(&init).map_err(|err| {
+                SshError(format!("failed to read {}: {}", init.display(), err))
+            })
+        })?;
+
+    /* not the nonce of the local shells: the remote host learns it */
+    let nonce = nonce();
+    let mut command = Command::new("ssh");
+    command.arg("-t").args(args).arg(remote_command(&rc, &nesting));
+
+    let capture = Capture::new(DEFAULT_MAX_OUTPUT, &nesting.session_dir, &nonce).with_remote();
+    let pty = Pty::open().map_err(|err| SshError(err.to_string()))?;
+    let status = pty
+        .run(command, capture, None)
+        .map_err(|err| SshError(err.to_string()))?;
+    Ok(status.code().unwrap_or(255))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+    use std::path::PathBuf;
+    use std::process::Stdio;
+
+    #[test]
+    fn remote_shell_command() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out");
+        let nesting = Nesting {
+            id: 42,
+            session_dir: PathBuf::from("/home/op/.tracker/sessions/42"),
+            depth: 1,
+            home: PathBuf::from("/home/op"),
+            pty: None,
+        };
+        /* hooks reporting what they got, quotes included */
+        let rc = format!(
+            "echo \"'$TRACKER_REMOTE' $TRACKER_ID $TRACKER_DEPTH $TRACKER_PTY $TRACKER_NONCE\" > {0}\n\
+             rmdir \"$TRACKER_SESSION_DIR\" && echo dir >> {0}\n\
+             exit\n",
+            quote(&out.display().to_string())
+        );
+
+        /* as run by the login shell of the remote user, keys typed before
+         * the nonce */
+        let nonce = "0123456789abcdef0123456789abcdef";
+        let mut shell = Command::new("sh")
+            .arg("-c")
+            .arg(remote_command(&rc, &nesting))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        write!(shell.stdin.take().unwrap(), "ls\nl{}\n", nonce).unwrap();
+        let shell = shell.wait_with_output().unwrap();
+        assert!(shell.status.success());
+        assert_eq!(shell.stdout, b"\x1b]6973;N\x07");
+        assert_eq!(fs::read_to_string(&out).unwrap(), format!("'1' 42 1 1 {}\ndir\n", nonce));
+    }
+}
@@ -19,6 +19,20 @@ pub fn whoami() -> String {
     whoami.to_string()
 }
 
+pub fn hostname() -> String {
+    // TODO: add windows funcionality
+    let hostname = Command::new("uname").arg("-n").output().expect("failed to get hostname").stdout;
+    let hostname = String::from_utf8(hostname).unwrap();
+    hostname.trim_end_matches("\n").to_string()
+}
+
+pub fn kernel_version() -> String {
+    // TODO: add windows funcionality
+    let kernel = Command::new("uname").arg("-r").output().expect("failed to get kernel version").stdout;
+    let kernel = String::from_utf8(kernel).unwrap();
+    kernel.trim_end_matches("\n").to_string()
+}
+
 pub fn ip_get_addr(interface: &str) -> IpAddr {
     // TODO: add Windows funcionality
     let re = Regex::new(r#"inet\s([0-9]{1,3}\.[0-9]{1,3}\.[0-9]{1,3}\.[0-9]{1,3})"#).unwrap();
@@ -36,6 +50,18 @@ pub fn ip_get_addr(interface: &str) -> IpAddr {
     ipaddr
 }
 
+/**
+ * Whether process `pid` is still running.
+ */
+#[cfg(not(target_os = "windows"))]
+pub fn process_alive(pid: u32) -> bool {
+    /* signal 0 checks the process without touching it */
+    if unsafe { libc::kill(pid as libc::pid_t, 0) } == 0 {
+        return true;
+    }
+    io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
 /**
  * Get current timestamp as seconds.
  */
@@ -46,6 +72,16 @@ pub fn timestamp_now() -> u64 {
         .as_secs()
 }
 
+/**
+ * Get current timestamp as milliseconds.
+ */
+pub fn timestamp_now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
 /**
  * Read file `filepath` from position `from`
  * and return contents readed.
@@ -60,21 +96,44 @@ pub fn file_read_from<P: AsRef<Path>>(
     let mut file = File::open(&filepath)?;
     let len = file.metadata().unwrap().len();
     if len < from {
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            format!(
-                "Cannot read file {} from position {}: file lenght is {}",
-                &filepath.as_ref().display(),
-                from,
-                len
-            ),
-        ));
+        return Err(io::Error::other(format!(
+            "Cannot read file {} from position {}: file lenght is {}",
+            &filepath.as_ref().display(),
+            from,
+            len
+        )));
     }
     file.seek(io::SeekFrom::Start(from)).unwrap();
     file.read_to_string(& mut contents)? as u64;
     Ok(contents)
 }
 
+/**
+ * Read file `filepath` from position `from`
+ * and return the raw bytes readed.
+ * Return some io::Error if present.
+ */
+pub fn file_read_bytes_from<P: AsRef<Path>>(
+    filepath: P,
+    from: u64,
+) -> io::Result<Vec<u8>> {
+    let mut contents = Vec::new();
+
+    let mut file = File::open(&filepath)?;
+    let len = file.metadata()?.len();
+    if len < from {
+        return Err(io::Error::other(format!(
+            "Cannot read file {} from position {}: file lenght is {}",
+            &filepath.as_ref().display(),
+            from,
+            len
+        )));
+    }
+    file.seek(io::SeekFrom::Start(from))?;
+    file.read_to_end(&mut contents)?;
+    Ok(contents)
+}
+
 /**
  * Copy file `filepath` to a temporary file and return path ot the copy.
  * Return some io::Error if present.
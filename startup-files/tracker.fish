@@ -0,0 +1,172 @@
+# tracker hooks for fish, installed as ~/.tracker/fish/tracker.fish:
+# tracker sources it with `fish --init-command`, after the user's own
+# config.fish and conf.d snippets. Sourced with --attach from the user's
+# config.fish, it attaches the shell to tracker daemon.
+
+if test "$argv[1]" = --attach
+    # sourced from the user's config.fish: attach the shell to `tracker
+    # daemon`. Skipped if the hooks are loaded already, or if the shell was
+    # started by tracker (a shell attached to the daemon attaches its children).
+    if set -q TRACKER_HOOKED; or begin; set -q TRACKER_ID; and not set -q TRACKER_ATTACHED; end
+        return 0
+    end
+    set -l bin tracker
+    if set -q TRACKER_BIN
+        set bin $TRACKER_BIN
+    end
+    $bin attach $fish_pid > /dev/null 2>&1; or return 0
+    set -gx TRACKER_ID $fish_pid
+    set -gx TRACKER_ATTACHED $fish_pid
+    set -gx TRACKER_SESSION_DIR "$HOME/.tracker/sessions/$fish_pid"
+    set -e TRACKER_PTY TRACKER_NONCE
+else if not set -q TRACKER_ID
+    echo "problem with environment: TRACKER_ID is not set"
+    exit
+end
+set -g TRACKER_HOOKED 1
+
+set -gx TRACKER_BASE "$HOME/.tracker"
+# per-session state, in the session dir created by tracker or the daemon
+if not set -q TRACKER_SESSION_DIR
+    set -gx TRACKER_SESSION_DIR "$TRACKER_BASE/sessions/$TRACKER_ID"
+end
+set -gx TRACKER_HISTLOG "$TRACKER_SESSION_DIR/hist.log"
+set -gx TRACKER_OUTPUTS "$TRACKER_SESSION_DIR/outputs"
+set -gx TRACKER_PAUSE_FILE "$TRACKER_SESSION_DIR/paused"
+
+mkdir -p "$TRACKER_OUTPUTS"
+touch "$TRACKER_HISTLOG"
+
+set -gx TRACKER_TTY ""
+if tty -s
+    set TRACKER_TTY (tty)
+end
+
+# current time in milliseconds
+function __trckr_now_ms
+    if test (uname) = Darwin
+        echo (date +%s)000
+    else
+        date +%s%3N
+    end
+end
+
+# print the JSON string literal of $argv[1]
+function __trckr_json_str
+    set -l lines (printf '%s' $argv[1] \
+        | string replace -a '\\' '\\\\' \
+        | string replace -a '"' '\\"' \
+        | string replace -a \t '\\t' \
+        | string replace -a \r '\\r')
+    set -l s (string join '\n' -- $lines)
+
+    # remaining control characters
+    for i in (seq 1 31)
+        set -l c (printf '%b' '\x'(printf '%02x' $i))
+        if string match -q -- "*$c*" "$s"
+            set s (string replace -a -- $c (printf '\\\\u%04x' $i) "$s")
+        end
+    end
+    printf '"%s"' "$s"
+end
+
+# tmux or GNU screen pane of the shell: multiplexer, session, window, pane.
+# Read at every record, as tmux windows can be moved and renumbered.
+function __trckr_mux
+    if set -q TMUX
+        set -l pane (tmux display-message -p -t "$TMUX_PANE" \
+            '#{session_name}'\t'#{window_index}'\t'#{pane_index}' 2> /dev/null \
+            | string split \t)
+        printf '%s\n' tmux "$pane[1]" "$pane[2]" "$pane[3]"
+    else if set -q STY
+        printf '%s\n' screen "$STY" "$WINDOW" ""
+    else
+        printf '%s\n' "" "" "" ""
+    end
+end
+
+# tell the tracker pty where a command starts ("S") or ends ("E", with the
+# name of the outfile), signed with the nonce of the session
+function __trckr_marker
+    if set -q TRACKER_PTY
+        printf '\e]6973;%s\a' $argv[1]
+    end
+end
+
+# shells, sudo, su and ssh started from the tracked shell are run by
+# tracker, which loads the hooks in the new shell to record to this session
+function __trckr_wrap
+    set -l bin tracker
+    if set -q TRACKER_BIN
+        set bin $TRACKER_BIN
+    end
+    if command -q $bin
+        $bin wrap $argv
+    else
+        command $argv
+    end
+end
+
+function bash; __trckr_wrap bash $argv; end
+function ssh; __trckr_wrap ssh $argv; end
+function zsh; __trckr_wrap zsh $argv; end
+function fish; __trckr_wrap fish $argv; end
+function sudo; __trckr_wrap sudo $argv; end
+function su; __trckr_wrap su $argv; end
+
+# trk pause|resume|tag <label>|note <text>|status: talk to the running
+# tracker from inside the shell
+function trk
+    if set -q TRACKER_BIN
+        $TRACKER_BIN ctl $argv
+    else
+        tracker ctl $argv
+    end
+end
+
+function __trckr_preexec --on-event fish_preexec
+    set -g TRACKER_CMD_TIMESTAMP (date +%s)
+    set -g TRACKER_CMD_CWD "$PWD"
+    __trckr_marker "S;$TRACKER_NONCE"
+end
+
+# fish_postexec: $argv[1] is the command line, $status and $CMD_DURATION
+# (milliseconds) are the ones of the command
+function __trckr_postexec --on-event fish_postexec
+    set -l exit_status $status
+    set -l duration $CMD_DURATION
+    set -l cmd $argv[1]
+
+    # ctrl-c or Enter pressed, or tracking paused
+    if test -z "$cmd"; or test -f "$TRACKER_PAUSE_FILE"
+        return
+    end
+
+    set -l end (__trckr_now_ms)
+    set -l start (math $end - $duration)
+    set -l cwd "$TRACKER_CMD_CWD"
+    if test -z "$cwd"
+        set cwd "$PWD"
+    end
+
+    # the output is stored in outfile by the tracker pty
+    set -l outfile ""
+    if set -q TRACKER_PTY
+        if test (uname) = Darwin
+            set outfile (mktemp -u "$TRACKER_OUTPUTS/out_XXXXXX")
+        else
+            set outfile (mktemp -u -p "$TRACKER_OUTPUTS" out_XXXXXX.txt)
+        end
+        __trckr_marker "E;$TRACKER_NONCE;"(basename $outfile)
+    end
+
+    # append record to logfile, one JSON object per line
+    set -l mux (__trckr_mux)
+    printf '{"status":%d,"user":%s,"timestamp":%d,"start":%d,"end":%d,"duration":%d,"pid":%d,"shlvl":%d,"depth":%d,"tty":%s,"cwd":%s,"cmd":%s,"outfile":%s,"mux":%s,"mux_session":%s,"mux_window":%s,"mux_pane":%s}\n' \
+        $exit_status (__trckr_json_str (whoami)) $TRACKER_CMD_TIMESTAMP $start $end $duration \
+        $fish_pid $SHLVL (set -q TRACKER_DEPTH; and echo $TRACKER_DEPTH; or echo 0) \
+        (__trckr_json_str "$TRACKER_TTY") (__trckr_json_str "$cwd") \
+        (__trckr_json_str "$cmd") (__trckr_json_str "$outfile") \
+        (__trckr_json_str "$mux[1]") (__trckr_json_str "$mux[2]") \
+        (__trckr_json_str "$mux[3]") (__trckr_json_str "$mux[4]") >> "$TRACKER_HISTLOG"
+end
@@ -0,0 +1,112 @@
+#![cfg(not(target_os = "windows"))]
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/* HOME with the bash hooks installed and attaching from .bashrc */
+fn home() -> PathBuf {
+    let home = tempfile::tempdir().unwrap().into_path();
+    fs::create_dir_all(home.join(".tracker")).unwrap();
+    fs::copy(
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("startup-files/tracker.rc"),
+        home.join(".tracker/.tracker.rc"),
+    )
+    .unwrap();
+    fs::write(home.join(".bashrc"), "source ~/.tracker/.tracker.rc --attach\n").unwrap();
+    home
+}
+
+fn command(home: &Path, program: &str) -> Command {
+    let mut command = Command::new(program);
+    command
+        .env("HOME", home)
+        .env("TRACKER_BIN", env!("CARGO_BIN_EXE_tracker"))
+        .env_remove("TRACKER_ID")
+        .env_remove("TRACKER_ATTACHED")
+        .env_remove("TRACKER_SESSION_DIR")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    command
+}
+
+fn wait_for(what: &str, mut done: impl FnMut() -> bool) {
+    let start = Instant::now();
+    while !done() {
+        assert!(start.elapsed() < Duration::from_secs(10), "timed out waiting for {}", what);
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+fn records(path: &Path) -> Vec<serde_json::Value> {
+    fs::read_to_string(path)
+        .unwrap_or_default()
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect()
+}
+
+fn actions(records: &[serde_json::Value], action: &str) -> usize {
+    records.iter().filter(|r| r["event.action"] == action).count()
+}
+
+#[test]
+fn shells_attach_to_daemon() {
+    let home = home();
+    let out = home.join("records.json");
+
+    let mut daemon: Child = command(&home, env!("CARGO_BIN_EXE_tracker"))
+        .args(["daemon", "tracker-test", "lo", "--output"])
+        .arg(format!("file:{}", out.display()))
+        .spawn()
+        .unwrap();
+    wait_for("the daemon socket", || home.join(".tracker/daemon.sock").exists());
+
+    /* two terminals, attached by the hook in .bashrc */
+    let mut shells: Vec<Child> = (0..2)
+        .map(|_| command(&home, "bash").arg("-i").spawn().unwrap())
+        .collect();
+    for i in 0..3 {
+        for (n, shell) in shells.iter_mut().enumerate() {
+            writeln!(shell.stdin.as_mut().unwrap(), "echo shell{} {}", n, i).unwrap();
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    /* answered at once, the event is written by the daemon afterwards */
+    writeln!(shells[0].stdin.as_mut().unwrap(), "trk tag shell0 && trk status").unwrap();
+    wait_for("the tag", || actions(&records(&out), "tag") == 1);
+    for shell in &mut shells {
+        writeln!(shell.stdin.as_mut().unwrap(), "exit").unwrap();
+        shell.wait().unwrap();
+    }
+    wait_for("the shells to detach", || actions(&records(&out), "session-end") == 2);
+
+    unsafe {
+        libc::kill(daemon.id() as libc::pid_t, libc::SIGTERM);
+    }
+    daemon.wait().unwrap();
+    assert!(!home.join(".tracker/daemon.sock").exists());
+
+    /* each shell is a session of its own, the daemon one too */
+    let records = records(&out);
+    assert_eq!(actions(&records, "session-start"), 3);
+    assert_eq!(actions(&records, "session-end"), 3);
+    for n in 0..2 {
+        let commands: Vec<&serde_json::Value> = records
+            .iter()
+            .filter(|r| {
+                r["process.command_line"]
+                    .as_str()
+                    .is_some_and(|c| c.starts_with(&format!("echo shell{}", n)))
+            })
+            .collect();
+        assert_eq!(commands.len(), 3);
+        assert!(commands.iter().all(|r| r["tracker.session.id"] == commands[0]["tracker.session.id"]));
+    }
+    let tag = records.iter().find(|r| r["event.action"] == "tag").unwrap();
+    assert_eq!(tag["tracker.tag"], "shell0");
+}
@@ -0,0 +1,94 @@
+#![cfg(not(target_os = "windows"))]
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/* HOME with the bash hooks installed and `bashrc` as the user's .bashrc */
+fn home(bashrc: &str) -> PathBuf {
+    let home = tempfile::tempdir().unwrap().into_path();
+    fs::create_dir_all(home.join(".tracker")).unwrap();
+    fs::copy(
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("startup-files/tracker.rc"),
+        home.join(".tracker/.tracker.rc"),
+    )
+    .unwrap();
+    fs::write(home.join(".bashrc"), bashrc).unwrap();
+    home
+}
+
+/* run the hooked bash, typing `lines`, and return the PROMPT_COMMAND it
+ * ends with: its attributes (-- or -a for an array), then its lines */
+fn prompt_command(home: &Path, lines: &[&str]) -> Vec<String> {
+    let mut bash = Command::new("bash")
+        .arg("--rcfile")
+        .arg(home.join(".tracker/.tracker.rc"))
+        .arg("-i")
+        .env("HOME", home)
+        .env("TRACKER_ID", "4000201")
+        .env_remove("PROMPT_COMMAND")
+        .env_remove("TRACKER_SESSION_DIR")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("failed to run bash");
+    {
+        let stdin = bash.stdin.as_mut().unwrap();
+        for line in lines {
+            writeln!(stdin, "{}", line).unwrap();
+        }
+        writeln!(
+            stdin,
+            "{{ declare -p PROMPT_COMMAND | cut -d' ' -f2; printf '%s\\n' \"${{PROMPT_COMMAND[@]}}\"; }} > ~/prompt_command"
+        )
+        .unwrap();
+        writeln!(stdin, "exit").unwrap();
+    }
+    bash.wait().unwrap();
+    fs::read_to_string(home.join("prompt_command"))
+        .unwrap()
+        .lines()
+        .map(|l| l.to_owned())
+        .collect()
+}
+
+fn prompts(home: &Path) -> Vec<String> {
+    fs::read_to_string(home.join("prompts"))
+        .unwrap_or_default()
+        .lines()
+        .map(|l| l.to_owned())
+        .collect()
+}
+
+#[test]
+fn string_prompt_command_chained() {
+    let home = home("PROMPT_COMMAND='echo \"user $?\" >> ~/prompts'\n");
+    let declared = prompt_command(&home, &["true", "false"]);
+
+    assert_eq!(declared, ["--", "prmpt_cmd", "echo \"user $?\" >> ~/prompts", "trckr_prompt_end"]);
+    /* the user's one still runs, with the status of the command */
+    let prompts = prompts(&home);
+    assert!(prompts.len() >= 3);
+    assert_eq!(prompts[1..3], ["user 0", "user 1"]);
+}
+
+#[test]
+fn array_prompt_command_chained() {
+    let home = home("PROMPT_COMMAND=('echo one >> ~/prompts' 'echo two >> ~/prompts')\n");
+    let declared = prompt_command(&home, &["true"]);
+
+    assert_eq!(
+        declared,
+        ["-a", "prmpt_cmd", "echo one >> ~/prompts", "echo two >> ~/prompts", "trckr_prompt_end"]
+    );
+    assert_eq!(prompts(&home)[..4], ["one", "two", "one", "two"]);
+}
+
+#[test]
+fn no_prompt_command() {
+    let home = home("");
+    let declared = prompt_command(&home, &["true"]);
+    assert_eq!(declared, ["--", "prmpt_cmd", "trckr_prompt_end"]);
+}
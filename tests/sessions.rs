@@ -0,0 +1,181 @@
+#![cfg(not(target_os = "windows"))]
+
+#[macro_use]
+extern crate lazy_static;
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+use tracker::console::*;
+
+lazy_static! {
+    /* temporary HOME with the bash hooks installed, shared by the tests */
+    static ref HOME: PathBuf = {
+        let home = tempfile::tempdir().unwrap().into_path();
+        fs::create_dir_all(home.join(".tracker")).unwrap();
+        fs::copy(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("startup-files/tracker.rc"),
+            home.join(".tracker/.tracker.rc"),
+        )
+        .unwrap();
+        std::env::set_var("HOME", &home);
+        home
+    };
+}
+
+fn home() -> PathBuf {
+    HOME.clone()
+}
+
+fn start_shell(ctrl: &ConsoleHistControl) -> Child {
+    let home = home();
+    Command::new("bash")
+        .arg("--rcfile")
+        .arg(home.join(".tracker/.tracker.rc"))
+        .arg("-i")
+        .env("HOME", &home)
+        .env("TRACKER_ID", ctrl.id().unwrap().to_string())
+        .env("TRACKER_SESSION_DIR", ctrl.session_dir())
+        .env("TRACKER_BIN", env!("CARGO_BIN_EXE_tracker"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("failed to run bash")
+}
+
+fn commands(ctrl: &mut ConsoleHistControl) -> Vec<String> {
+    ctrl.update().unwrap();
+    ctrl.history().iter().map(|e| e.cmd.clone()).collect()
+}
+
+#[test]
+fn sessions_side_by_side() {
+    home();
+    let mut a = ConsoleHistControl::new();
+    let mut b = ConsoleHistControl::new();
+    a.init(4_000_001).unwrap();
+    b.init(4_000_002).unwrap();
+    assert_ne!(a.session_dir(), b.session_dir());
+
+    let mut shell_a = start_shell(&a);
+    let mut shell_b = start_shell(&b);
+    {
+        let stdin_a = shell_a.stdin.as_mut().unwrap();
+        let stdin_b = shell_b.stdin.as_mut().unwrap();
+        for i in 0..5 {
+            writeln!(stdin_a, "echo a{}", i).unwrap();
+            writeln!(stdin_b, "echo b{}", i).unwrap();
+            thread::sleep(Duration::from_millis(100));
+        }
+        writeln!(stdin_a, "exit").unwrap();
+        writeln!(stdin_b, "exit").unwrap();
+    }
+    shell_a.wait().unwrap();
+    shell_b.wait().unwrap();
+
+    let expected = |s: &str| (0..5).map(|i| format!("echo {}{}", s, i)).collect::<Vec<String>>();
+    assert_eq!(commands(&mut a), expected("a"));
+    assert_eq!(commands(&mut b), expected("b"));
+}
+
+#[test]
+fn session_id_collision() {
+    home();
+    let mut first = ConsoleHistControl::new();
+    first.init(4_000_003).unwrap();
+
+    /* the id is taken while the first tracker runs */
+    let mut second = ConsoleHistControl::new();
+    assert!(second.init(4_000_003).is_err());
+
+    /* then its dir is moved aside and the id reused */
+    fs::write(first.session_dir().join("hist.log"), "{}\n").unwrap();
+    let dir = first.session_dir().to_path_buf();
+    drop(first);
+    second.init(4_000_003).unwrap();
+    assert_eq!(second.session_dir(), dir.as_path());
+    assert!(!dir.join("hist.log").exists());
+    let moved = fs::read_dir(dir.parent().unwrap())
+        .unwrap()
+        .filter_map(|e| e.ok())
+        .any(|e| e.file_name().to_string_lossy().starts_with("4000003."));
+    assert!(moved);
+}
+
+#[test]
+fn nested_shell_records_to_session() {
+    home();
+    let mut ctrl = ConsoleHistControl::new();
+    ctrl.init(4_000_004).unwrap();
+
+    let mut shell = start_shell(&ctrl);
+    {
+        let stdin = shell.stdin.as_mut().unwrap();
+        for line in &["echo top", "bash -i", "echo nested", "exit", "echo back", "exit"] {
+            writeln!(stdin, "{}", line).unwrap();
+            thread::sleep(Duration::from_millis(200));
+        }
+    }
+    shell.wait().unwrap();
+
+    ctrl.update().unwrap();
+    let history: Vec<(String, u64)> = ctrl.history().iter().map(|e| (e.cmd.clone(), e.depth)).collect();
+    assert_eq!(
+        history,
+        vec![
+            ("echo top".to_owned(), 0),
+            ("echo nested".to_owned(), 1),
+            ("bash -i".to_owned(), 0),
+            ("echo back".to_owned(), 0),
+        ]
+    );
+}
+
+#[test]
+fn session_dir_removed() {
+    home();
+    let mut ctrl = ConsoleHistControl::new();
+    ctrl.init(4_000_005).unwrap();
+    let dir = ctrl.session_dir().to_path_buf();
+
+    /* kept while a record waits for its output */
+    let record = format!(
+        r#"{{"status":0,"timestamp":1600000000,"end":{},"cmd":"ls","outfile":"{}"}}"#,
+        tracker::utils::timestamp_now_ms(),
+        dir.join("outputs/out_1.txt").display()
+    );
+    fs::write(dir.join("hist.log"), format!("{}\n", record)).unwrap();
+    ctrl.update().unwrap();
+    assert!(ctrl.remove_session_dir().is_err());
+    assert!(dir.exists());
+
+    /* then removed, once the histlog is read */
+    fs::write(dir.join("hist.log"), "").unwrap();
+    ctrl.remove_session_dir().unwrap();
+    assert!(!dir.exists());
+    assert!(ctrl.id().is_none());
+}
+
+#[test]
+fn stale_session_dirs_pruned() {
+    home();
+    let sessions = session_dir(4_000_006).unwrap().parent().unwrap().to_path_buf();
+    let now = tracker::utils::timestamp_now_ms();
+    let old = sessions.join(format!("4000006.{}", now - 8 * 24 * 3600 * 1000));
+    let recent = sessions.join(format!("4000006.{}", now - 24 * 3600 * 1000));
+    let other = sessions.join("4000006.notes");
+    for dir in &[&old, &recent, &other] {
+        fs::create_dir_all(dir).unwrap();
+    }
+
+    let mut ctrl = ConsoleHistControl::new();
+    ctrl.init(4_000_006).unwrap();
+    assert!(!old.exists());
+    assert!(recent.exists());
+    assert!(other.exists());
+}
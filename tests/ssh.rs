@@ -0,0 +1,168 @@
+#![cfg(not(target_os = "windows"))]
+
+#[macro_use]
+extern crate lazy_static;
+
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+use tracker::console::*;
+
+/* stand-in for ssh: keeps its arguments, joins the remote command as ssh
+ * does and runs it here with the shell of the user on a new terminal, as
+ * sshd would */
+const FAKE_SSH: &str = r#"#!/bin/sh
+printf '%s\n' "$@" > "$HOME/ssh_args.$TRACKER_ID"
+while [ "$1" != op@jump ]; do shift; done; shift
+SSH_CONNECTION="192.0.2.1 50000 192.0.2.7 22" SHELL=/bin/sh exec script -qec "$*" /dev/null
+"#;
+
+/* a remote host that asks the nonce and forges markers with it, then runs
+ * an untracked shell */
+const HOSTILE_SSH: &str = r#"#!/bin/sh
+stty -echo; printf '\033]6973;N\007'; IFS= read -r nonce; stty echo
+printf '\033]6973;S;%s\007pwned\r\n' "$nonce"
+printf '\033]6973;E;%s;%s\007' "$nonce" pwned "$nonce" ../pwned "$nonce" "$PWNED"
+printf '\033]6973;R;%s;{"status":0,"timestamp":1,"cmd":"x","tracker.session.id":"1"}\007' "$nonce"
+printf '\033]6973;R;%s;{"status":0,"timestamp":1,"cmd":"x","user.name":"root"}\007' "$nonce"
+printf '\033]6973;R;%s;{"status":0,"timestamp":1,"cmd":"signed"}\007' "$nonce"
+exec sh
+"#;
+
+/* printed by a remote command: markers without the nonce, and an ask for
+ * it */
+const FORGED: &str = "\x1b]6973;R;{\"status\":0,\"timestamp\":1,\"cmd\":\"forged\"}\x07\
+                      \x1b]6973;R;bad;{\"status\":0,\"timestamp\":1,\"cmd\":\"forged\"}\x07\
+                      \x1b]6973;S\x07\x1b]6973;E;pwned\x07\x1b]6973;N\x07";
+
+lazy_static! {
+    /* temporary HOME with the bash hooks installed and the fake ssh in bin
+     * dirs, shared by the tests */
+    static ref HOME: PathBuf = {
+        let home = tempfile::tempdir().unwrap().into_path();
+        fs::create_dir_all(home.join(".tracker")).unwrap();
+        fs::copy(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("startup-files/tracker.rc"),
+            home.join(".tracker/.tracker.rc"),
+        )
+        .unwrap();
+        for (dir, ssh) in &[("bin", FAKE_SSH), ("hostile", HOSTILE_SSH)] {
+            let bin = home.join(dir);
+            fs::create_dir_all(&bin).unwrap();
+            fs::write(bin.join("ssh"), ssh).unwrap();
+            fs::set_permissions(bin.join("ssh"), fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        std::env::set_var("HOME", &home);
+        home
+    };
+}
+
+/* run `tracker ssh` with the ssh of `bin`, typing `lines` in the remote
+ * shell once it got the nonce */
+fn tracker_ssh(ctrl: &ConsoleHistControl, bin: &str, lines: &[&str]) {
+    let home = HOME.clone();
+    let path = format!("{}:{}", home.join(bin).display(), std::env::var("PATH").unwrap());
+    let mut ssh = Command::new(env!("CARGO_BIN_EXE_tracker"))
+        .args(["ssh", "op@jump"])
+        .env("HOME", &home)
+        .env("PATH", path)
+        .env("PWNED", home.join("pwned"))
+        .env("TRACKER_ID", ctrl.id().unwrap().to_string())
+        .env("TRACKER_SESSION_DIR", ctrl.session_dir())
+        .env_remove("TRACKER_DEPTH")
+        .env_remove("TRACKER_HOME")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    {
+        let stdin = ssh.stdin.as_mut().unwrap();
+        for line in lines {
+            thread::sleep(Duration::from_millis(500));
+            writeln!(stdin, "{}", line).unwrap();
+        }
+    }
+    assert!(ssh.wait().unwrap().success());
+}
+
+#[test]
+fn remote_commands_recorded() {
+    lazy_static::initialize(&HOME);
+    let mut ctrl = ConsoleHistControl::new();
+    ctrl.init(4_000_101).unwrap();
+
+    tracker_ssh(&ctrl, "bin", &["echo remote $TRACKER_REMOTE", "exit"]);
+
+    ctrl.update().unwrap();
+    let history = ctrl.history();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].cmd, "echo remote $TRACKER_REMOTE");
+    assert_eq!(history[0].output.trim(), "remote 1");
+    assert_eq!(history[0].depth, 1);
+    let host = history[0].host.as_ref().unwrap();
+    assert_eq!(host.ip, "192.0.2.7");
+    assert_eq!(host.name, tracker::utils::hostname());
+}
+
+#[test]
+fn nonce_kept_off_the_command_line() {
+    lazy_static::initialize(&HOME);
+    let mut ctrl = ConsoleHistControl::new();
+    ctrl.init(4_000_103).unwrap();
+
+    tracker_ssh(&ctrl, "bin", &["echo \"$TRACKER_NONCE\" > ~/nonce.$TRACKER_ID", "exit"]);
+
+    let nonce = fs::read_to_string(HOME.join("nonce.4000103")).unwrap();
+    let nonce = nonce.trim_end();
+    assert_eq!(nonce.len(), 32);
+    assert!(nonce.chars().all(|c| c.is_ascii_hexdigit()));
+    let args = fs::read_to_string(HOME.join("ssh_args.4000103")).unwrap();
+    assert!(args.starts_with("-t\nop@jump\n"));
+    assert!(!args.contains(nonce));
+    ctrl.update().unwrap();
+    assert_eq!(ctrl.history().len(), 1);
+}
+
+#[test]
+fn forged_remote_output_rejected() {
+    lazy_static::initialize(&HOME);
+    let mut ctrl = ConsoleHistControl::new();
+    ctrl.init(4_000_104).unwrap();
+    fs::write(HOME.join("forged"), FORGED).unwrap();
+
+    let line = "cat ~/forged; read -t 0.2 leaked; echo \"leaked [$leaked]\"";
+    tracker_ssh(&ctrl, "bin", &[line, "exit"]);
+
+    assert!(!ctrl.session_dir().join("outputs").join("pwned").exists());
+    ctrl.update().unwrap();
+    let history = ctrl.history();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].cmd, line);
+    assert_eq!(history[0].output.trim(), "leaked []");
+}
+
+#[test]
+fn hostile_remote_markers_ignored() {
+    lazy_static::initialize(&HOME);
+    let mut ctrl = ConsoleHistControl::new();
+    ctrl.init(4_000_102).unwrap();
+    let outputs = ctrl.session_dir().join("outputs");
+    fs::create_dir_all(&outputs).unwrap();
+
+    tracker_ssh(&ctrl, "hostile", &["exit"]);
+
+    assert!(!HOME.join("pwned").exists());
+    assert!(!ctrl.session_dir().join("pwned").exists());
+    /* only the record of the hooks' fields, with an outfile of tracker */
+    ctrl.update().unwrap();
+    let history = ctrl.history();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].cmd, "signed");
+    assert_eq!(fs::read_dir(&outputs).unwrap().count(), 3);
+}